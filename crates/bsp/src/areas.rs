@@ -0,0 +1,167 @@
+use glam::Vec3;
+use std::collections::HashMap;
+
+use crate::{lumps::BspAreaPortal, Bsp};
+
+impl Bsp {
+    /// Returns the area containing `point`, 0 if it's outside of the world
+    pub fn area_at(&self, point: Vec3) -> usize {
+        self.leafs
+            .get(self.find_leaf(point))
+            .map_or(0, |l| l.area() as usize)
+    }
+
+    /// Returns the portals leading out of an area
+    pub fn area_portals_of(&self, area: usize) -> &[BspAreaPortal] {
+        let Some(area) = self.areas.get(area) else {
+            return &[];
+        };
+
+        let first = area.first_area_portal.max(0) as usize;
+        let count = area.num_area_portals.max(0) as usize;
+        self.area_portals
+            .get(first..first + count)
+            .unwrap_or_default()
+    }
+
+    /// Returns the polygon an area portal covers
+    pub fn area_portal_polygon(&self, portal: &BspAreaPortal) -> Vec<Vec3> {
+        let first = portal.first_clip_portal_vert as usize;
+        self.clip_portal_verts
+            .iter()
+            .skip(first)
+            .take(portal.clip_portal_verts as usize)
+            .map(|&v| v.into())
+            .collect()
+    }
+
+    /// Returns the initial state of every `func_areaportal` and `func_areaportalwindow`, keyed by portal key
+    ///
+    /// Windows only fade out with distance, so they're always open. Portals without an entity are always closed
+    pub fn areaportal_states(&self) -> eyre::Result<HashMap<u16, bool>> {
+        let entities = self.parse_entities()?;
+        let portals = entities.by_classname("func_areaportal").filter_map(|e| {
+            let key = e.get("portalnumber")?.parse().ok()?;
+            // Doors set this to match their own state
+            let open = e.get("StartOpen").is_none_or(|v| v.trim() != "0");
+            Some((key, open))
+        });
+        let windows = entities
+            .by_classname("func_areaportalwindow")
+            .filter_map(|e| Some((e.get("portalnumber")?.parse().ok()?, true)));
+
+        Ok(portals.chain(windows).collect())
+    }
+
+    /// Returns which areas can be seen from `area`, indexed by area number
+    ///
+    /// `is_open` is called with the key of every portal that's crossed
+    pub fn connected_areas(&self, area: usize, is_open: impl Fn(u16) -> bool) -> Vec<bool> {
+        let mut connected = vec![false; self.areas.len()];
+        // Area 0 is the solid area, which doesn't connect to anything
+        if area == 0 || area >= self.areas.len() {
+            return connected;
+        }
+
+        let mut stack = vec![area];
+        connected[area] = true;
+        while let Some(area) = stack.pop() {
+            for portal in self.area_portals_of(area) {
+                let other = portal.other_area as usize;
+                if other < connected.len() && !connected[other] && is_open(portal.portal_key) {
+                    connected[other] = true;
+                    stack.push(other);
+                }
+            }
+        }
+
+        connected
+    }
+
+    pub fn areas_connected(&self, a: usize, b: usize, is_open: impl Fn(u16) -> bool) -> bool {
+        self.connected_areas(a, is_open)
+            .get(b)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lumps::BspArea;
+
+    fn portal(portal_key: u16, other_area: u16) -> BspAreaPortal {
+        BspAreaPortal {
+            portal_key,
+            other_area,
+            first_clip_portal_vert: 0,
+            clip_portal_verts: 0,
+            plane_num: 0,
+        }
+    }
+
+    #[test]
+    fn portal_connectivity() {
+        // Three rooms in a row, separated by portals 1 and 2
+        let bsp = Bsp {
+            entities: "{\n\"classname\" \"func_areaportal\"\n\"portalnumber\" \"1\"\n\"StartOpen\" \"0\"\n}\n{\n\"classname\" \"func_areaportal\"\n\"portalnumber\" \"2\"\n}\n".to_string(),
+            areas: vec![
+                BspArea {
+                    num_area_portals: 0,
+                    first_area_portal: 0,
+                },
+                BspArea {
+                    num_area_portals: 1,
+                    first_area_portal: 0,
+                },
+                BspArea {
+                    num_area_portals: 2,
+                    first_area_portal: 1,
+                },
+                BspArea {
+                    num_area_portals: 1,
+                    first_area_portal: 3,
+                },
+            ],
+            area_portals: vec![portal(1, 2), portal(1, 1), portal(2, 3), portal(2, 2)],
+            ..Default::default()
+        };
+
+        let states = bsp.areaportal_states().unwrap();
+        assert_eq!(states, HashMap::from([(1, false), (2, true)]));
+
+        let is_open = |key| states.get(&key).copied().unwrap_or(false);
+        assert_eq!(bsp.connected_areas(2, is_open), [false, false, true, true]);
+        assert!(!bsp.areas_connected(1, 3, is_open));
+        assert!(bsp.areas_connected(1, 3, |_| true));
+    }
+
+    #[test]
+    fn areaportal_windows_are_open() {
+        let bsp = Bsp {
+            entities: "{\n\"classname\" \"func_areaportalwindow\"\n\"portalnumber\" \"1\"\n\"FadeStartDist\" \"128\"\n}\n".to_string(),
+            areas: vec![
+                BspArea {
+                    num_area_portals: 0,
+                    first_area_portal: 0,
+                },
+                BspArea {
+                    num_area_portals: 1,
+                    first_area_portal: 0,
+                },
+                BspArea {
+                    num_area_portals: 1,
+                    first_area_portal: 1,
+                },
+            ],
+            area_portals: vec![portal(1, 2), portal(1, 1)],
+            ..Default::default()
+        };
+
+        let states = bsp.areaportal_states().unwrap();
+        assert_eq!(states, HashMap::from([(1, true)]));
+        let is_open = |key| states.get(&key).copied().unwrap_or(false);
+        assert!(bsp.areas_connected(1, 2, is_open));
+    }
+}
@@ -0,0 +1,693 @@
+use glam::Vec3;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use crate::{entities::BspEntity, Bsp, BspHeader, BSP_LUMP_NAMES};
+
+/// Props closer than this are considered to be in the same place
+const PROP_EPSILON: f32 = 0.01;
+
+/// Differences between two versions of a map
+#[derive(Debug, Clone, Default)]
+pub struct BspDiff {
+    pub lumps: Vec<BspLumpDelta>,
+    pub entities: Vec<BspEntityChange>,
+    pub static_props: Vec<BspStaticPropChange>,
+    pub textures: Vec<BspTextureChange>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BspLumpDelta {
+    pub index: usize,
+    /// Uncompressed size
+    pub old_size: u32,
+    pub new_size: u32,
+}
+
+/// How an entity is matched between both maps
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BspEntityKey {
+    HammerId(i32),
+    Targetname(String),
+    /// Fallback for entities without a name, so they only match if they haven't moved
+    Position {
+        classname: String,
+        origin: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BspEntityChange {
+    Added {
+        key: BspEntityKey,
+        classname: String,
+    },
+    Removed {
+        key: BspEntityKey,
+        classname: String,
+    },
+    Changed {
+        key: BspEntityKey,
+        classname: String,
+        properties: Vec<BspPropertyChange>,
+    },
+}
+
+/// A changed key/value pair. Keys that appear multiple times (outputs) are compared per value
+#[derive(Debug, Clone, PartialEq)]
+pub struct BspPropertyChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BspStaticPropChange {
+    Added {
+        model: String,
+        origin: Vec3,
+    },
+    Removed {
+        model: String,
+        origin: Vec3,
+    },
+    Moved {
+        model: String,
+        old_origin: Vec3,
+        new_origin: Vec3,
+        old_angles: Vec3,
+        new_angles: Vec3,
+    },
+}
+
+/// A material whose face count changed, a count of 0 means it was added or removed
+#[derive(Debug, Clone, PartialEq)]
+pub struct BspTextureChange {
+    pub material: String,
+    pub old_faces: usize,
+    pub new_faces: usize,
+}
+
+impl BspDiff {
+    /// Compares entities, static props and texture usage
+    pub fn new(old: &Bsp, new: &Bsp) -> eyre::Result<Self> {
+        Ok(Self {
+            lumps: vec![],
+            entities: diff_entities(
+                &old.parse_entities()?.entities,
+                &new.parse_entities()?.entities,
+            ),
+            static_props: diff_static_props(old, new),
+            textures: diff_textures(old, new),
+        })
+    }
+
+    /// Adds lump size deltas, which aren't available from a parsed [`Bsp`]
+    pub fn with_headers(mut self, old: &BspHeader, new: &BspHeader) -> Self {
+        let size = |header: &BspHeader, index: usize| {
+            header.lumps.get(index).map_or(0, |l| {
+                l.uncompressed_length(header.endian).unwrap_or(l.length)
+            })
+        };
+
+        self.lumps = (0..BSP_LUMP_NAMES.len())
+            .map(|index| BspLumpDelta {
+                index,
+                old_size: size(old, index),
+                new_size: size(new, index),
+            })
+            .filter(|d| d.old_size != d.new_size)
+            .collect();
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lumps.is_empty()
+            && self.entities.is_empty()
+            && self.static_props.is_empty()
+            && self.textures.is_empty()
+    }
+}
+
+impl BspEntityKey {
+    fn of(entity: &BspEntity) -> Self {
+        if let Some(id) = entity.hammer_id() {
+            BspEntityKey::HammerId(id)
+        } else if let Some(name) = entity.targetname().filter(|n| !n.is_empty()) {
+            BspEntityKey::Targetname(name.to_lowercase())
+        } else {
+            BspEntityKey::Position {
+                classname: entity.classname().unwrap_or_default().to_lowercase(),
+                origin: entity.get("origin").unwrap_or_default().to_string(),
+            }
+        }
+    }
+}
+
+fn diff_entities(old: &[BspEntity], new: &[BspEntity]) -> Vec<BspEntityChange> {
+    let old = keyed(old);
+    let new = keyed(new);
+    let new_map: HashMap<_, _> = new.iter().map(|(k, e)| (k, *e)).collect();
+    let old_map: HashMap<_, _> = old.iter().map(|(k, e)| (k, *e)).collect();
+    let classname = |e: &BspEntity| e.classname().unwrap_or_default().to_string();
+
+    let mut changes = vec![];
+    for (k, old_entity) in &old {
+        match new_map.get(k) {
+            None => changes.push(BspEntityChange::Removed {
+                key: k.0.clone(),
+                classname: classname(old_entity),
+            }),
+            Some(new_entity) => {
+                let properties = diff_properties(old_entity, new_entity);
+                if !properties.is_empty() {
+                    changes.push(BspEntityChange::Changed {
+                        key: k.0.clone(),
+                        classname: classname(new_entity),
+                        properties,
+                    });
+                }
+            }
+        }
+    }
+
+    for (k, new_entity) in &new {
+        if !old_map.contains_key(k) {
+            changes.push(BspEntityChange::Added {
+                key: k.0.clone(),
+                classname: classname(new_entity),
+            });
+        }
+    }
+
+    changes
+}
+
+/// Pairs entities with their key and how many entities used that key before them, so duplicate
+/// keys are matched in file order
+fn keyed(entities: &[BspEntity]) -> Vec<((BspEntityKey, usize), &BspEntity)> {
+    let mut seen: HashMap<BspEntityKey, usize> = HashMap::new();
+    entities
+        .iter()
+        .map(|e| {
+            let key = BspEntityKey::of(e);
+            let n = seen.entry(key.clone()).or_default();
+            *n += 1;
+            ((key, *n), e)
+        })
+        .collect()
+}
+
+fn diff_properties(old: &BspEntity, new: &BspEntity) -> Vec<BspPropertyChange> {
+    let mut keys: Vec<String> = vec![];
+    for (k, _) in old.properties.iter().chain(&new.properties) {
+        if !keys.iter().any(|key| key.eq_ignore_ascii_case(k)) {
+            keys.push(k.clone());
+        }
+    }
+
+    let mut changes = vec![];
+    for key in keys {
+        let old_values: Vec<_> = old.get_all(&key).collect();
+        let new_values: Vec<_> = new.get_all(&key).collect();
+        if old_values == new_values {
+            continue;
+        }
+
+        if old_values.len() <= 1 && new_values.len() <= 1 {
+            changes.push(BspPropertyChange {
+                key: key.clone(),
+                old: old_values.first().map(|v| v.to_string()),
+                new: new_values.first().map(|v| v.to_string()),
+            });
+            continue;
+        }
+
+        let mut added = new_values.clone();
+        for value in &old_values {
+            match added.iter().position(|v| v == value) {
+                Some(i) => {
+                    added.remove(i);
+                }
+                None => changes.push(BspPropertyChange {
+                    key: key.clone(),
+                    old: Some(value.to_string()),
+                    new: None,
+                }),
+            }
+        }
+        changes.extend(added.into_iter().map(|v| BspPropertyChange {
+            key: key.clone(),
+            old: None,
+            new: Some(v.to_string()),
+        }));
+    }
+
+    changes
+}
+
+/// Props have no identity, so they're matched by model. Unmoved props are paired first, the rest
+/// are paired with the closest remaining prop using the same model
+fn diff_static_props(old: &Bsp, new: &Bsp) -> Vec<BspStaticPropChange> {
+    let by_model = |bsp: &Bsp| {
+        let mut models: HashMap<String, Vec<(Vec3, Vec3)>> = HashMap::new();
+        for prop in &bsp.static_props {
+            let model = prop
+                .model(&bsp.static_prop_models)
+                .unwrap_or_default()
+                .to_lowercase();
+            models
+                .entry(model)
+                .or_default()
+                .push((prop.origin.into(), prop.angles.into()));
+        }
+        models
+    };
+
+    let old_models = by_model(old);
+    let mut new_models = by_model(new);
+    let mut model_names: Vec<_> = old_models
+        .keys()
+        .chain(new_models.keys())
+        .cloned()
+        .collect();
+    model_names.sort();
+    model_names.dedup();
+
+    let same = |a: &(Vec3, Vec3), b: &(Vec3, Vec3)| {
+        a.0.abs_diff_eq(b.0, PROP_EPSILON) && a.1.abs_diff_eq(b.1, PROP_EPSILON)
+    };
+
+    let mut changes = vec![];
+    for model in model_names {
+        let mut old_props = old_models.get(&model).cloned().unwrap_or_default();
+        let mut new_props = new_models.remove(&model).unwrap_or_default();
+
+        old_props.retain(|o| match new_props.iter().position(|n| same(o, n)) {
+            Some(i) => {
+                new_props.swap_remove(i);
+                false
+            }
+            None => true,
+        });
+
+        for (old_origin, old_angles) in old_props {
+            let closest = new_props
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    a.0.distance_squared(old_origin)
+                        .total_cmp(&b.0.distance_squared(old_origin))
+                })
+                .map(|(i, _)| i);
+
+            match closest {
+                Some(i) => {
+                    let (new_origin, new_angles) = new_props.swap_remove(i);
+                    changes.push(BspStaticPropChange::Moved {
+                        model: model.clone(),
+                        old_origin,
+                        new_origin,
+                        old_angles,
+                        new_angles,
+                    });
+                }
+                None => changes.push(BspStaticPropChange::Removed {
+                    model: model.clone(),
+                    origin: old_origin,
+                }),
+            }
+        }
+
+        changes.extend(
+            new_props
+                .into_iter()
+                .map(|(origin, _)| BspStaticPropChange::Added {
+                    model: model.clone(),
+                    origin,
+                }),
+        );
+    }
+
+    changes
+}
+
+fn diff_textures(old: &Bsp, new: &Bsp) -> Vec<BspTextureChange> {
+    let face_counts = |bsp: &Bsp| {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for face in &bsp.faces {
+            if let Some(material) = bsp.face_material(face) {
+                *counts.entry(material.to_lowercase()).or_default() += 1;
+            }
+        }
+        counts
+    };
+
+    let old_counts = face_counts(old);
+    let new_counts = face_counts(new);
+    let mut materials: Vec<_> = old_counts.keys().chain(new_counts.keys()).collect();
+    materials.sort();
+    materials.dedup();
+
+    materials
+        .into_iter()
+        .map(|material| BspTextureChange {
+            material: material.clone(),
+            old_faces: old_counts.get(material).copied().unwrap_or(0),
+            new_faces: new_counts.get(material).copied().unwrap_or(0),
+        })
+        .filter(|c| c.old_faces != c.new_faces)
+        .collect()
+}
+
+impl Display for BspLumpDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} bytes ({:+})",
+            BSP_LUMP_NAMES[self.index],
+            self.old_size,
+            self.new_size,
+            self.new_size as i64 - self.old_size as i64
+        )
+    }
+}
+
+impl Display for BspEntityKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BspEntityKey::HammerId(id) => write!(f, "hammerid {id}"),
+            BspEntityKey::Targetname(name) => write!(f, "'{name}'"),
+            BspEntityKey::Position { origin, .. } => write!(f, "at ({origin})"),
+        }
+    }
+}
+
+impl Display for BspEntityChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BspEntityChange::Added { key, classname } => write!(f, "+ {classname} {key}"),
+            BspEntityChange::Removed { key, classname } => write!(f, "- {classname} {key}"),
+            BspEntityChange::Changed {
+                key,
+                classname,
+                properties,
+            } => {
+                write!(f, "~ {classname} {key}")?;
+                for p in properties {
+                    write!(f, "\n    {p}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for BspPropertyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "\"{}\" \"{old}\" -> \"{new}\"", self.key),
+            (None, Some(new)) => write!(f, "+ \"{}\" \"{new}\"", self.key),
+            (Some(old), None) => write!(f, "- \"{}\" \"{old}\"", self.key),
+            (None, None) => write!(f, "\"{}\"", self.key),
+        }
+    }
+}
+
+impl Display for BspStaticPropChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BspStaticPropChange::Added { model, origin } => write!(f, "+ {model} at {origin}"),
+            BspStaticPropChange::Removed { model, origin } => write!(f, "- {model} at {origin}"),
+            BspStaticPropChange::Moved {
+                model,
+                old_origin,
+                new_origin,
+                old_angles,
+                new_angles,
+            } => {
+                write!(f, "~ {model} moved from {old_origin} to {new_origin}")?;
+                if !old_angles.abs_diff_eq(*new_angles, PROP_EPSILON) {
+                    write!(f, ", rotated from {old_angles} to {new_angles}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for BspTextureChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.old_faces, self.new_faces) {
+            (0, new) => write!(f, "+ {} ({new} faces)", self.material),
+            (old, 0) => write!(f, "- {} ({old} faces)", self.material),
+            (old, new) => write!(f, "~ {} ({old} -> {new} faces)", self.material),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::BspEntities,
+        flags::BspSurfaceFlags,
+        gamelumps::StaticPropLump,
+        lumps::{BspFace, BspTexData, BspTexInfo},
+        profile::BspLumpLayout,
+        BspLump, BSP_LUMP_COUNT,
+    };
+    use binrw::{BinReaderExt, Endian};
+    use std::io::Cursor;
+
+    #[test]
+    fn entity_changes() {
+        let old = BspEntities::parse(
+            "{\n\"classname\" \"worldspawn\"\n\"hammerid\" \"1\"\n}\n{\n\"classname\" \"logic_relay\"\n\"targetname\" \"relay\"\n\"OnTrigger\" \"a,Open,,0,-1\"\n\"OnTrigger\" \"b,Open,,0,-1\"\n}\n{\n\"classname\" \"light\"\n\"origin\" \"0 0 0\"\n}\n",
+        )
+        .unwrap();
+        let new = BspEntities::parse(
+            "{\n\"classname\" \"worldspawn\"\n\"hammerid\" \"1\"\n}\n{\n\"classname\" \"logic_relay\"\n\"targetname\" \"Relay\"\n\"OnTrigger\" \"b,Open,,0,-1\"\n\"OnTrigger\" \"c,Open,,0,-1\"\n\"spawnflags\" \"1\"\n}\n{\n\"classname\" \"light\"\n\"origin\" \"0 0 64\"\n}\n",
+        )
+        .unwrap();
+
+        let changes = diff_entities(&old.entities, &new.entities);
+        assert_eq!(changes.len(), 3);
+        let BspEntityChange::Changed {
+            key, properties, ..
+        } = &changes[0]
+        else {
+            panic!("expected the relay to be changed, got {}", changes[0]);
+        };
+        assert_eq!(*key, BspEntityKey::Targetname("relay".to_string()));
+        assert_eq!(properties.len(), 4);
+        assert_eq!(properties[1].old.as_deref(), Some("a,Open,,0,-1"));
+        assert_eq!(properties[2].new.as_deref(), Some("c,Open,,0,-1"));
+        assert_eq!(properties[3].key, "spawnflags");
+
+        // Unnamed entities that moved can't be matched
+        assert!(matches!(changes[1], BspEntityChange::Removed { .. }));
+        assert!(matches!(changes[2], BspEntityChange::Added { .. }));
+    }
+
+    /// A version 4 prop, which has no optional fields
+    fn prop(origin: [f32; 3], yaw: f32) -> StaticPropLump {
+        let mut data = vec![];
+        for v in origin.into_iter().chain([0.0, yaw, 0.0]) {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend([0; 32]);
+        Cursor::new(data).read_le_args((4, false)).unwrap()
+    }
+
+    fn props(models: &[&str], props: Vec<StaticPropLump>) -> Bsp {
+        Bsp {
+            static_prop_models: models.iter().map(|m| m.to_string()).collect(),
+            static_props: props,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn static_prop_changes() {
+        let mut barrel = prop([0.0, 64.0, 0.0], 0.0);
+        barrel.model_index = 1;
+        let old = props(
+            &["models/crate.mdl", "models/barrel.mdl"],
+            vec![
+                prop([0.0, 0.0, 0.0], 0.0),
+                prop([10.0, 0.0, 0.0], 0.0),
+                prop([100.0, 0.0, 0.0], 0.0),
+                barrel,
+            ],
+        );
+        let new = props(
+            &["models/Crate.mdl"],
+            vec![
+                prop([500.0, 0.0, 0.0], 0.0),
+                prop([100.0, 0.0, 0.0], 90.0),
+                prop([20.0, 0.0, 0.0], 0.0),
+                prop([10.0, 0.0, 0.0], 0.0),
+            ],
+        );
+
+        let changes = diff_static_props(&old, &new);
+        let model = "models/crate.mdl".to_string();
+        assert_eq!(
+            changes,
+            [
+                BspStaticPropChange::Removed {
+                    model: "models/barrel.mdl".to_string(),
+                    origin: Vec3::new(0.0, 64.0, 0.0),
+                },
+                // The prop at 10 stays paired with itself instead of being taken by its neighbor
+                BspStaticPropChange::Moved {
+                    model: model.clone(),
+                    old_origin: Vec3::ZERO,
+                    new_origin: Vec3::new(20.0, 0.0, 0.0),
+                    old_angles: Vec3::ZERO,
+                    new_angles: Vec3::ZERO,
+                },
+                BspStaticPropChange::Moved {
+                    model: model.clone(),
+                    old_origin: Vec3::new(100.0, 0.0, 0.0),
+                    new_origin: Vec3::new(100.0, 0.0, 0.0),
+                    old_angles: Vec3::ZERO,
+                    new_angles: Vec3::new(0.0, 90.0, 0.0),
+                },
+                BspStaticPropChange::Added {
+                    model,
+                    origin: Vec3::new(500.0, 0.0, 0.0),
+                },
+            ]
+        );
+        assert_eq!(
+            changes[2].to_string(),
+            "~ models/crate.mdl moved from [100, 0, 0] to [100, 0, 0], rotated from [0, 0, 0] to [0, 90, 0]"
+        );
+    }
+
+    fn face(tex_info: i16) -> BspFace {
+        BspFace {
+            plane_num: 0,
+            side: 0,
+            on_node: 0,
+            first_edge: 0,
+            num_edges: 0,
+            tex_info,
+            disp_info: -1,
+            surface_fog_volume_id: -1,
+            styles: [255; 4],
+            lightmap_data_offset: -1,
+            area: 0.0,
+            lightmap_mins: [0, 0],
+            lightmap_size: [0, 0],
+            orig_face: 0,
+            num_primitives: 0,
+            first_primitive: 0,
+            smoothing_groups: 0,
+        }
+    }
+
+    /// One texinfo per material, faces reference them by index
+    fn textured(materials: &[&str], faces: &[i16]) -> Bsp {
+        Bsp {
+            faces: faces.iter().map(|&ti| face(ti)).collect(),
+            tex_info: (0..materials.len() as i32)
+                .map(|tex_data| BspTexInfo {
+                    texture_vecs: [[0.0; 4]; 2],
+                    lightmap_vecs: [[0.0; 4]; 2],
+                    flags: BspSurfaceFlags::empty(),
+                    tex_data,
+                })
+                .collect(),
+            tex_data: (0..materials.len() as i32)
+                .map(|name_index| BspTexData {
+                    reflectivity: [0.0; 3],
+                    name_index,
+                    width: 64,
+                    height: 64,
+                    view_width: 64,
+                    view_height: 64,
+                })
+                .collect(),
+            texdata_string_table: materials.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn texture_changes() {
+        let old = textured(&["BRICK/WALL", "dev/dev"], &[0, 0, 1]);
+        let new = textured(&["brick/wall", "metal/floor"], &[0, 1, -1]);
+
+        let changes: Vec<String> = diff_textures(&old, &new)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "~ brick/wall (2 -> 1 faces)",
+                "- dev/dev (1 faces)",
+                "+ metal/floor (1 faces)",
+            ]
+        );
+    }
+
+    #[test]
+    fn lump_size_changes() {
+        let header = |endian, lumps: &[(usize, u32, u32)]| {
+            let mut header = BspHeader {
+                version: 20,
+                lumps: vec![
+                    BspLump {
+                        offset: 0,
+                        length: 0,
+                        version: 0,
+                        fourcc: [0; 4],
+                    };
+                    BSP_LUMP_COUNT
+                ],
+                map_revision: 0,
+                endian,
+                lump_layout: BspLumpLayout::Standard,
+            };
+            for &(index, length, uncompressed) in lumps {
+                header.lumps[index].length = length;
+                header.lumps[index].fourcc = match endian {
+                    Endian::Little => uncompressed.to_le_bytes(),
+                    Endian::Big => uncompressed.to_be_bytes(),
+                };
+            }
+            header
+        };
+
+        // Compressed lumps are compared by their uncompressed size, in the byte order of the map
+        let old = header(Endian::Little, &[(1, 100, 0), (3, 50, 200), (7, 40, 300)]);
+        let new = header(
+            Endian::Big,
+            &[(1, 100, 0), (3, 60, 200), (5, 10, 0), (7, 320, 0)],
+        );
+
+        let diff = BspDiff::default().with_headers(&old, &new);
+        assert_eq!(
+            diff.lumps,
+            [
+                BspLumpDelta {
+                    index: 5,
+                    old_size: 0,
+                    new_size: 10,
+                },
+                BspLumpDelta {
+                    index: 7,
+                    old_size: 300,
+                    new_size: 320,
+                },
+            ]
+        );
+        assert_eq!(diff.lumps[1].to_string(), "faces: 300 -> 320 bytes (+20)");
+    }
+}
@@ -0,0 +1,398 @@
+use eyre::{ensure, OptionExt};
+use glam::{Vec2, Vec3};
+
+use crate::{
+    flags::BspDispTriTags,
+    lumps::{BspDispInfo, BspFace},
+    Bsp,
+};
+
+/// Vertices closer than this are considered shared between neighboring displacements
+const STITCH_EPSILON: f32 = 0.1;
+
+/// Triangulated displacement surface
+///
+/// Vertices are stored in a `verts_wide * verts_wide` grid, starting at the corner closest to the displacement's start position
+#[derive(Debug, Clone)]
+pub struct DispMesh {
+    /// Index into the dispinfo lump
+    pub disp_info: usize,
+    pub verts_wide: usize,
+    pub positions: Vec<Vec3>,
+    /// Position of every vertex before it was displaced
+    pub base_positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    /// Blend factor between the two textures of a blended material, from 0 to 1
+    pub alphas: Vec<f32>,
+    /// Position of every vertex on the base face, from 0 to 1
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<u32>,
+    /// One entry for every triangle in `indices`
+    pub tri_tags: Vec<BspDispTriTags>,
+}
+
+impl DispMesh {
+    /// Returns the grid indices of the vertices on the border of the mesh
+    fn border(&self) -> impl Iterator<Item = usize> + '_ {
+        let w = self.verts_wide;
+        (0..w * w).filter(move |i| {
+            let (x, y) = (i % w, i / w);
+            x == 0 || y == 0 || x == w - 1 || y == w - 1
+        })
+    }
+
+    fn compute_normals(&mut self) {
+        self.normals = vec![Vec3::ZERO; self.positions.len()];
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.positions[tri[i] as usize]);
+            // Not normalized, so larger triangles contribute more
+            let n = (b - a).cross(c - a);
+            for &i in tri {
+                self.normals[i as usize] += n;
+            }
+        }
+
+        for n in &mut self.normals {
+            *n = n.normalize_or_zero();
+        }
+    }
+}
+
+impl Bsp {
+    /// Builds the displacement surface for `face`, without stitching it to its neighbors
+    pub fn build_displacement(&self, face: &BspFace) -> eyre::Result<DispMesh> {
+        ensure!(face.disp_info >= 0, "Face is not a displacement");
+        let disp_index = face.disp_info as usize;
+        let dispinfo = self
+            .disp_info
+            .get(disp_index)
+            .ok_or_eyre("Displacement info out of bounds")?;
+
+        let corners = self.face_vertices(face);
+        ensure!(
+            corners.len() == 4,
+            "Displacement face has {} vertices, expected 4",
+            corners.len()
+        );
+        ensure!(
+            (1..=4).contains(&dispinfo.power),
+            "Invalid displacement power {}",
+            dispinfo.power
+        );
+
+        // The displacement grid starts at the corner closest to the start position
+        let low_base = Vec3::from(dispinfo.start_position);
+        let base_i = (0..4)
+            .min_by(|&a, &b| {
+                let da = (corners[a] - low_base).abs().element_sum();
+                let db = (corners[b] - low_base).abs().element_sum();
+                da.total_cmp(&db)
+            })
+            .unwrap();
+
+        let high_base = corners[(base_i + 3) % 4];
+        let high_ray = corners[(base_i + 2) % 4] - high_base;
+        let low_ray = corners[(base_i + 1) % 4] - low_base;
+
+        let verts_wide = (1 << dispinfo.power) + 1;
+        let vert_count = verts_wide * verts_wide;
+        let first_vert = dispinfo.disp_vert_start as usize;
+        let verts = self
+            .disp_verts
+            .get(first_vert..first_vert + vert_count)
+            .ok_or_eyre("Displacement vertices out of bounds")?;
+
+        let mut positions = Vec::with_capacity(vert_count);
+        let mut base_positions = Vec::with_capacity(vert_count);
+        let mut alphas = Vec::with_capacity(vert_count);
+        let mut uvs = Vec::with_capacity(vert_count);
+        for y in 0..verts_wide {
+            let fy = y as f32 / (verts_wide as f32 - 1.0);
+            let mid_base = low_base + low_ray * fy;
+            let mid_ray = high_base + high_ray * fy - mid_base;
+
+            for x in 0..verts_wide {
+                let fx = x as f32 / (verts_wide as f32 - 1.0);
+                let vert = &verts[y * verts_wide + x];
+
+                let base = mid_base + mid_ray * fx;
+                positions.push(base + Vec3::from(vert.vec) * vert.dist);
+                base_positions.push(base);
+                alphas.push(vert.alpha / 255.0);
+                uvs.push(Vec2::new(fx, fy));
+            }
+        }
+
+        let quads_wide = verts_wide - 1;
+        let mut indices = Vec::with_capacity(quads_wide * quads_wide * 6);
+        for y in 0..quads_wide {
+            for x in 0..quads_wide {
+                let i = (y * verts_wide + x) as u32;
+                let w = verts_wide as u32;
+                // Alternate the diagonal to get the engine's diamond pattern
+                if i.is_multiple_of(2) {
+                    indices.extend_from_slice(&[i, i + 1, i + w, i + 1, i + w + 1, i + w]);
+                } else {
+                    indices.extend_from_slice(&[i, i + w + 1, i + w, i + 1, i + w + 1, i]);
+                }
+            }
+        }
+
+        let tri_count = indices.len() / 3;
+        let first_tri = dispinfo.disp_tri_start.max(0) as usize;
+        let tri_tags = match self.disp_tris.get(first_tri..first_tri + tri_count) {
+            Some(tris) => tris.iter().map(|t| t.tags).collect(),
+            None => vec![BspDispTriTags::empty(); tri_count],
+        };
+
+        let mut mesh = DispMesh {
+            disp_info: disp_index,
+            verts_wide,
+            positions,
+            base_positions,
+            normals: vec![],
+            alphas,
+            uvs,
+            indices,
+            tri_tags,
+        };
+        mesh.compute_normals();
+
+        Ok(mesh)
+    }
+
+    /// Builds all displacements and smooths the normals along edges and corners shared with their neighbors
+    ///
+    /// The returned meshes are indexed like the dispinfo lump
+    pub fn build_displacements(&self) -> Vec<Option<DispMesh>> {
+        let mut meshes = vec![None; self.disp_info.len()];
+        for face in &self.faces {
+            if face.disp_info < 0 {
+                continue;
+            }
+
+            if let Some(slot) = meshes.get_mut(face.disp_info as usize) {
+                if slot.is_none() {
+                    *slot = self.build_displacement(face).ok();
+                }
+            }
+        }
+
+        let stitched: Vec<_> = (0..meshes.len())
+            .map(|i| stitch_normals(&meshes, i, &self.disp_info[i]))
+            .collect();
+        for (mesh, normals) in meshes.iter_mut().zip(stitched) {
+            if let (Some(mesh), Some(normals)) = (mesh, normals) {
+                mesh.normals = normals;
+            }
+        }
+
+        meshes
+    }
+}
+
+/// Averages the border normals of mesh `index` with the matching vertices of its neighbors
+fn stitch_normals(
+    meshes: &[Option<DispMesh>],
+    index: usize,
+    dispinfo: &BspDispInfo,
+) -> Option<Vec<Vec3>> {
+    let mesh = meshes[index].as_ref()?;
+
+    let mut neighbors: Vec<usize> = dispinfo
+        .edge_neighbors
+        .iter()
+        .flat_map(|n| n.sub_neighbors.iter().filter_map(|s| s.neighbor()))
+        .chain(
+            dispinfo
+                .corner_neighbors
+                .iter()
+                .flat_map(|c| c.neighbors().iter().map(|&n| n as usize)),
+        )
+        .filter(|&n| n != index)
+        .collect();
+    neighbors.sort_unstable();
+    neighbors.dedup();
+
+    let mut normals = mesh.normals.clone();
+    for i in mesh.border() {
+        let p = mesh.positions[i];
+        for n in &neighbors {
+            let Some(Some(other)) = meshes.get(*n) else {
+                continue;
+            };
+
+            if let Some(j) = other
+                .border()
+                .find(|&j| other.positions[j].distance_squared(p) < STITCH_EPSILON * STITCH_EPSILON)
+            {
+                normals[i] += other.normals[j];
+            }
+        }
+
+        normals[i] = normals[i].normalize_or_zero();
+    }
+
+    Some(normals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lumps::{BspDispCornerNeighbors, BspDispNeighbor, BspDispSubNeighbor, BspDispVert};
+
+    fn face(first_edge: i32, disp_info: i16) -> BspFace {
+        BspFace {
+            plane_num: 0,
+            side: 0,
+            on_node: 0,
+            first_edge,
+            num_edges: 4,
+            tex_info: 0,
+            disp_info,
+            surface_fog_volume_id: -1,
+            styles: [255; 4],
+            lightmap_data_offset: -1,
+            area: 0.0,
+            lightmap_mins: [0, 0],
+            lightmap_size: [0, 0],
+            orig_face: 0,
+            num_primitives: 0,
+            first_primitive: 0,
+            smoothing_groups: 0,
+        }
+    }
+
+    fn disp_info(
+        start_position: [f32; 3],
+        disp_vert_start: i32,
+        neighbor: (usize, u16),
+    ) -> BspDispInfo {
+        let none = BspDispSubNeighbor {
+            neighbor: 0xFFFF,
+            neighbor_orientation: 0,
+            span: 0,
+            neighbor_span: 0,
+        };
+        let mut edge_neighbors = [BspDispNeighbor {
+            sub_neighbors: [none; 2],
+        }; 4];
+        edge_neighbors[neighbor.0].sub_neighbors[0].neighbor = neighbor.1;
+
+        BspDispInfo {
+            start_position,
+            disp_vert_start,
+            disp_tri_start: -1,
+            power: 2,
+            min_tess: 0,
+            smoothing_angle: 0.0,
+            contents: Default::default(),
+            map_face: 0,
+            lightmap_alpha_start: 0,
+            lightmap_sample_position_start: 0,
+            edge_neighbors,
+            corner_neighbors: [BspDispCornerNeighbors {
+                neighbors: [0; 4],
+                count: 0,
+            }; 4],
+            allowed_verts: [0; 10],
+        }
+    }
+
+    /// Two 64 unit power 2 displacements next to each other, the first flat and the second sloping up along x
+    fn two_displacements() -> Bsp {
+        let mut disp_verts = vec![];
+        for slope in [0.0, 8.0] {
+            for i in 0..25 {
+                disp_verts.push(BspDispVert {
+                    vec: [0.0, 0.0, 1.0],
+                    dist: (i % 5) as f32 * slope,
+                    alpha: i as f32 * 10.0,
+                });
+            }
+        }
+
+        Bsp {
+            vertices: vec![
+                [0.0, 0.0, 0.0],
+                [0.0, 64.0, 0.0],
+                [64.0, 64.0, 0.0],
+                [64.0, 0.0, 0.0],
+                [128.0, 64.0, 0.0],
+                [128.0, 0.0, 0.0],
+            ],
+            edges: vec![
+                [0, 1],
+                [1, 2],
+                [2, 3],
+                [3, 0],
+                [3, 2],
+                [2, 4],
+                [4, 5],
+                [5, 3],
+            ],
+            surfedges: (0..8).collect(),
+            faces: vec![face(0, 0), face(4, 1)],
+            disp_info: vec![
+                disp_info([0.0, 0.0, 0.0], 0, (2, 1)),
+                disp_info([64.0, 0.0, 0.0], 25, (0, 0)),
+            ],
+            disp_verts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn build_power_2_displacement() {
+        let bsp = two_displacements();
+        let mesh = bsp.build_displacement(&bsp.faces[0]).unwrap();
+
+        assert_eq!(mesh.verts_wide, 5);
+        assert_eq!(mesh.positions.len(), 25);
+        assert_eq!(mesh.indices.len(), 16 * 6);
+        assert_eq!(mesh.tri_tags.len(), 32);
+
+        let corners = [0, 4, 20, 24].map(|i| mesh.positions[i]);
+        assert_eq!(
+            corners,
+            [
+                Vec3::ZERO,
+                Vec3::new(64.0, 0.0, 0.0),
+                Vec3::new(0.0, 64.0, 0.0),
+                Vec3::new(64.0, 64.0, 0.0),
+            ]
+        );
+        assert_eq!(mesh.uvs[24], Vec2::ONE);
+        assert_eq!(mesh.alphas[0], 0.0);
+        assert_eq!(mesh.alphas[12], 120.0 / 255.0);
+        assert!(mesh.normals.iter().all(|&n| n == Vec3::Z));
+
+        // The sloped displacement starts at its own start position
+        let sloped = bsp.build_displacement(&bsp.faces[1]).unwrap();
+        assert_eq!(sloped.positions[0], Vec3::new(64.0, 0.0, 0.0));
+        assert_eq!(sloped.positions[24], Vec3::new(128.0, 64.0, 32.0));
+
+        assert!(bsp.build_displacement(&face(0, -1)).is_err());
+    }
+
+    #[test]
+    fn stitch_neighbor_normals() {
+        let bsp = two_displacements();
+        let meshes = bsp.build_displacements();
+        let (flat, sloped) = (meshes[0].as_ref().unwrap(), meshes[1].as_ref().unwrap());
+        let slope_normal = Vec3::new(-0.5, 0.0, 1.0).normalize();
+        let shared_normal = (Vec3::Z + slope_normal).normalize();
+
+        // Vertices on the shared edge get the same, averaged normal
+        for y in 0..5 {
+            let (i, j) = (y * 5 + 4, y * 5);
+            assert_eq!(flat.positions[i], sloped.positions[j]);
+            assert!(flat.normals[i].abs_diff_eq(shared_normal, 1e-5));
+            assert!(sloped.normals[j].abs_diff_eq(shared_normal, 1e-5));
+        }
+
+        // Everything away from the shared edge keeps its own normal
+        assert_eq!(flat.normals[10], Vec3::Z);
+        assert!(sloped.normals[14].abs_diff_eq(slope_normal, 1e-5));
+    }
+}
@@ -0,0 +1,328 @@
+use eyre::{bail, ensure};
+use glam::Vec3;
+use std::fmt::{self, Display, Write};
+
+use crate::Bsp;
+
+/// Parsed entity lump
+///
+/// Entities that aren't modified are written back exactly as they were read
+#[derive(Debug, Clone, Default)]
+pub struct BspEntities {
+    pub entities: Vec<BspEntity>,
+    /// Anything after the last entity
+    trailing: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BspEntity {
+    /// Key/value pairs in file order. Keys can appear multiple times, which is how outputs are stored
+    pub properties: Vec<(String, String)>,
+    /// Source text and the properties it was parsed into
+    original: Option<(String, Vec<(String, String)>)>,
+}
+
+/// An entity I/O connection, eg. `"OnTrigger" "door,Open,,0,-1"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BspEntityOutput {
+    pub output: String,
+    pub target: String,
+    pub input: String,
+    pub parameter: String,
+    pub delay: f32,
+    /// -1 fires an unlimited amount of times
+    pub times_to_fire: i32,
+    /// Older maps separate fields with commas, newer ones use ESC (0x1B)
+    pub separator: char,
+}
+
+impl BspEntities {
+    pub fn parse(text: &str) -> eyre::Result<Self> {
+        let mut entities = vec![];
+        let mut rest = text;
+        let mut span_start = 0;
+
+        loop {
+            let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '\0');
+            if trimmed.is_empty() {
+                break;
+            }
+
+            ensure!(
+                trimmed.starts_with('{'),
+                "Expected '{{' at offset {}",
+                text.len() - trimmed.len()
+            );
+            let mut cursor = &trimmed[1..];
+            let mut properties = vec![];
+            loop {
+                cursor = cursor.trim_start();
+                if let Some(after) = cursor.strip_prefix('}') {
+                    cursor = after;
+                    break;
+                }
+
+                let (key, after) = read_quoted(cursor)?;
+                let (value, after) = read_quoted(after.trim_start())?;
+                properties.push((key.to_string(), value.to_string()));
+                cursor = after;
+            }
+
+            // Include a single line break after the closing brace
+            let cursor = cursor
+                .strip_prefix("\r\n")
+                .or_else(|| cursor.strip_prefix('\n'))
+                .unwrap_or(cursor);
+
+            let span_end = text.len() - cursor.len();
+            entities.push(BspEntity {
+                original: Some((text[span_start..span_end].to_string(), properties.clone())),
+                properties,
+            });
+
+            span_start = span_end;
+            rest = cursor;
+        }
+
+        Ok(Self {
+            entities,
+            trailing: text[span_start..].to_string(),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BspEntity> {
+        self.entities.iter()
+    }
+
+    pub fn by_classname<'a>(&'a self, classname: &'a str) -> impl Iterator<Item = &'a BspEntity> {
+        self.entities.iter().filter(move |e| {
+            e.classname()
+                .is_some_and(|c| c.eq_ignore_ascii_case(classname))
+        })
+    }
+
+    pub fn by_targetname<'a>(&'a self, targetname: &'a str) -> impl Iterator<Item = &'a BspEntity> {
+        self.entities.iter().filter(move |e| {
+            e.targetname()
+                .is_some_and(|c| c.eq_ignore_ascii_case(targetname))
+        })
+    }
+
+    pub fn worldspawn(&self) -> Option<&BspEntity> {
+        self.by_classname("worldspawn").next()
+    }
+}
+
+impl Display for BspEntities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entity in &self.entities {
+            entity.fmt(f)?;
+        }
+
+        f.write_str(&self.trailing)
+    }
+}
+
+impl BspEntity {
+    pub fn new(classname: &str) -> Self {
+        Self {
+            properties: vec![("classname".to_string(), classname.to_string())],
+            original: None,
+        }
+    }
+
+    /// Returns the first value for `key`. Keys are case insensitive
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.properties
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Replaces the first value for `key`, or adds it if it doesn't exist yet
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        match self
+            .properties
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        {
+            Some((_, v)) => *v = value.into(),
+            None => self.properties.push((key.to_string(), value.into())),
+        }
+    }
+
+    /// Adds a key/value pair, even if the key already exists
+    pub fn push(&mut self, key: &str, value: impl Into<String>) {
+        self.properties.push((key.to_string(), value.into()));
+    }
+
+    /// Removes every value for `key`
+    pub fn remove(&mut self, key: &str) {
+        self.properties
+            .retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    pub fn targetname(&self) -> Option<&str> {
+        self.get("targetname")
+    }
+
+    pub fn hammer_id(&self) -> Option<i32> {
+        self.get("hammerid")?.parse().ok()
+    }
+
+    pub fn origin(&self) -> Option<Vec3> {
+        parse_vector(self.get("origin")?)
+    }
+
+    pub fn angles(&self) -> Option<Vec3> {
+        parse_vector(self.get("angles")?)
+    }
+
+    /// Returns all properties that are formatted like an output connection
+    pub fn outputs(&self) -> impl Iterator<Item = BspEntityOutput> + '_ {
+        self.properties
+            .iter()
+            .filter_map(|(k, v)| BspEntityOutput::parse(k, v))
+    }
+
+    pub fn add_output(&mut self, output: &BspEntityOutput) {
+        self.push(&output.output, output.value());
+    }
+
+    /// Whether this entity was changed since it was parsed
+    pub fn is_modified(&self) -> bool {
+        self.original
+            .as_ref()
+            .is_none_or(|(_, properties)| *properties != self.properties)
+    }
+}
+
+impl Display for BspEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((text, _)) = self.original.as_ref().filter(|_| !self.is_modified()) {
+            return f.write_str(text);
+        }
+
+        f.write_str("{\n")?;
+        for (k, v) in &self.properties {
+            writeln!(f, "\"{k}\" \"{v}\"")?;
+        }
+        f.write_str("}\n")
+    }
+}
+
+impl BspEntityOutput {
+    pub fn parse(key: &str, value: &str) -> Option<Self> {
+        let separator = if value.contains('\x1b') { '\x1b' } else { ',' };
+        let mut parts = value.split(separator);
+        let target = parts.next()?;
+        let input = parts.next()?;
+        let parameter = parts.next()?;
+        let delay = parts.next()?.trim().parse().ok()?;
+        let times_to_fire = parts.next()?.trim().parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            output: key.to_string(),
+            target: target.to_string(),
+            input: input.to_string(),
+            parameter: parameter.to_string(),
+            delay,
+            times_to_fire,
+            separator,
+        })
+    }
+
+    /// Formats the connection as an entity value
+    pub fn value(&self) -> String {
+        let mut s = String::new();
+        let sep = self.separator;
+        let _ = write!(
+            s,
+            "{}{sep}{}{sep}{}{sep}{}{sep}{}",
+            self.target, self.input, self.parameter, self.delay, self.times_to_fire
+        );
+        s
+    }
+}
+
+impl Bsp {
+    pub fn parse_entities(&self) -> eyre::Result<BspEntities> {
+        BspEntities::parse(&self.entities)
+    }
+
+    pub fn set_entities(&mut self, entities: &BspEntities) {
+        self.entities = entities.to_string();
+    }
+}
+
+fn read_quoted(s: &str) -> eyre::Result<(&str, &str)> {
+    let Some(s) = s.strip_prefix('"') else {
+        bail!("Expected a quoted string, got {:?}", s.chars().next());
+    };
+
+    match s.find('"') {
+        Some(end) => Ok((&s[..end], &s[end + 1..])),
+        None => bail!("Unterminated string"),
+    }
+}
+
+fn parse_vector(s: &str) -> Option<Vec3> {
+    let mut parts = s.split_whitespace().map(|p| p.parse::<f32>());
+    let x = parts.next()?.ok()?;
+    let y = parts.next()?.ok()?;
+    let z = parts.next()?.ok()?;
+    Some(Vec3::new(x, y, z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LUMP: &str = "{\n\"world_maxs\" \"1 2 3\"\n\"classname\" \"worldspawn\"\n}\n{\n\"origin\" \"0 0 64\"\n\"targetname\" \"relay\"\n\"classname\" \"logic_relay\"\n\"OnTrigger\" \"door,Open,,0,-1\"\n\"OnTrigger\" \"door\x1bClose\x1b\x1b5.5\x1b1\"\n}\n";
+
+    #[test]
+    fn roundtrip_unchanged() {
+        let ents = BspEntities::parse(LUMP).unwrap();
+        assert_eq!(ents.entities.len(), 2);
+        assert_eq!(ents.to_string(), LUMP);
+    }
+
+    #[test]
+    fn edit_and_outputs() {
+        let mut ents = BspEntities::parse(LUMP).unwrap();
+        let relay = &mut ents.entities[1];
+        assert_eq!(relay.origin(), Some(Vec3::new(0.0, 0.0, 64.0)));
+
+        let outputs: Vec<_> = relay.outputs().collect();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].input, "Open");
+        assert_eq!(outputs[1].delay, 5.5);
+        assert_eq!(outputs[1].value(), "door\x1bClose\x1b\x1b5.5\x1b1");
+
+        relay.set("targetname", "relay2");
+        let text = ents.to_string();
+        assert!(text.starts_with("{\n\"world_maxs\" \"1 2 3\"\n\"classname\" \"worldspawn\"\n}\n"));
+        assert!(text.contains("\"targetname\" \"relay2\""));
+        assert_eq!(
+            BspEntities::parse(&text)
+                .unwrap()
+                .by_targetname("relay2")
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,696 @@
+//! Map geometry export to Wavefront OBJ and binary glTF 2.0
+//!
+//! Exported geometry is converted from Source's Z-up coordinate system to Y-up, but keeps its units (1 unit = 1 inch)
+
+use eyre::ensure;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use std::{collections::HashMap, fmt::Write as _, io::Write};
+
+use crate::{flags::BspSurfaceFlags, gamelumps::angles_to_quat, lumps::BspFace, Bsp};
+
+/// Triangle mesh in Source coordinates, with counter-clockwise front faces
+#[derive(Debug, Clone, Default)]
+pub struct ExportMesh {
+    pub name: String,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub primitives: Vec<ExportPrimitive>,
+}
+
+/// Triangles sharing a material
+#[derive(Debug, Clone, Default)]
+pub struct ExportPrimitive {
+    pub material: String,
+    pub indices: Vec<u32>,
+}
+
+/// A placed mesh. Props whose model couldn't be loaded are kept as empty instances
+#[derive(Debug, Clone)]
+pub struct ExportInstance {
+    pub name: String,
+    pub mesh: Option<usize>,
+    pub transform: Mat4,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportScene {
+    pub meshes: Vec<ExportMesh>,
+    pub instances: Vec<ExportInstance>,
+}
+
+/// Supplies static prop geometry, which is stored in external MDL files
+pub trait PropModelProvider {
+    fn load_model(&mut self, path: &str) -> Option<ExportMesh>;
+}
+
+/// Surfaces that are never drawn in-game
+const SKIPPED_SURFACES: BspSurfaceFlags = BspSurfaceFlags::NODRAW
+    .union(BspSurfaceFlags::SKIP)
+    .union(BspSurfaceFlags::HINT)
+    .union(BspSurfaceFlags::TRIGGER);
+
+impl ExportMesh {
+    fn primitive(&mut self, material: &str) -> &mut ExportPrimitive {
+        let index = match self.primitives.iter().position(|p| p.material == material) {
+            Some(i) => i,
+            None => {
+                self.primitives.push(ExportPrimitive {
+                    material: material.to_string(),
+                    indices: vec![],
+                });
+                self.primitives.len() - 1
+            }
+        };
+
+        &mut self.primitives[index]
+    }
+}
+
+impl Bsp {
+    /// Builds the mesh for a brush model, model 0 being the world. Displacements are included for the world only
+    pub fn model_mesh(&self, model_index: usize) -> ExportMesh {
+        let mut mesh = ExportMesh {
+            name: if model_index == 0 {
+                "world".to_string()
+            } else {
+                format!("*{model_index}")
+            },
+            ..Default::default()
+        };
+
+        let Some(model) = self.models.get(model_index) else {
+            return mesh;
+        };
+
+        let displacements = if model_index == 0 {
+            self.build_displacements()
+        } else {
+            vec![]
+        };
+
+        let first = model.first_face.max(0) as usize;
+        for face in self
+            .faces
+            .iter()
+            .skip(first)
+            .take(model.num_faces.max(0) as usize)
+        {
+            let Some(ti) = self.tex_info.get(face.tex_info as usize) else {
+                continue;
+            };
+            if ti.flags.intersects(SKIPPED_SURFACES) {
+                continue;
+            }
+
+            let material = self.face_material(face).unwrap_or_default();
+            let tex_size = match self.tex_data.get(ti.tex_data as usize) {
+                Some(td) => Vec2::new(td.width.max(1) as f32, td.height.max(1) as f32),
+                None => Vec2::ONE,
+            };
+
+            let base = mesh.positions.len() as u32;
+            let texture_positions: &[Vec3];
+            let indices: Vec<u32> = if face.disp_info >= 0 {
+                let Some(Some(disp)) = displacements.get(face.disp_info as usize) else {
+                    continue;
+                };
+
+                mesh.positions.extend_from_slice(&disp.positions);
+                mesh.normals.extend_from_slice(&disp.normals);
+                texture_positions = &disp.base_positions;
+                disp.indices.iter().map(|i| base + i).collect()
+            } else {
+                let vertices = self.face_vertices(face);
+                if vertices.len() < 3 {
+                    continue;
+                }
+
+                let normal = self.face_normal(face);
+                mesh.positions.extend_from_slice(&vertices);
+                mesh.normals
+                    .extend(std::iter::repeat_n(normal, vertices.len()));
+                texture_positions = &mesh.positions[base as usize..];
+
+                // Source faces are clockwise, exported ones counter-clockwise
+                self.face_triangles(face)
+                    .chunks_exact(3)
+                    .flat_map(|t| [t[0], t[2], t[1]])
+                    .map(|i| base + i)
+                    .collect()
+            };
+
+            // Displacements are textured from their undisplaced base face, like the engine does
+            let tu = Vec4::from(ti.texture_vecs[0]);
+            let tv = Vec4::from(ti.texture_vecs[1]);
+            for p in texture_positions {
+                let p = p.extend(1.0);
+                mesh.uvs.push(Vec2::new(tu.dot(p), tv.dot(p)) / tex_size);
+            }
+
+            mesh.primitive(material).indices.extend(indices);
+        }
+
+        mesh
+    }
+
+    fn face_normal(&self, face: &BspFace) -> Vec3 {
+        let normal = self
+            .planes
+            .get(face.plane_num as usize)
+            .map(|p| Vec3::from(p.normal))
+            .unwrap_or(Vec3::Z);
+        if face.side != 0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    /// Collects the world, brush entities and static props into a scene
+    ///
+    /// Static props are only given geometry when `props` can load their model
+    pub fn export_scene(&self, mut props: Option<&mut dyn PropModelProvider>) -> ExportScene {
+        let mut scene = ExportScene::default();
+
+        // Brush entities reference their model as "*N"
+        let mut brush_transforms = HashMap::new();
+        if let Ok(entities) = self.parse_entities() {
+            for entity in entities.iter() {
+                let Some(index) = entity
+                    .get("model")
+                    .and_then(|m| m.strip_prefix('*'))
+                    .and_then(|m| m.parse::<usize>().ok())
+                else {
+                    continue;
+                };
+
+                let origin = entity.origin().unwrap_or_default();
+                let angles = entity.angles().unwrap_or_default();
+                brush_transforms.insert(
+                    index,
+                    Mat4::from_rotation_translation(angles_to_quat(angles.into()), origin),
+                );
+            }
+        }
+
+        for model_index in 0..self.models.len() {
+            let mesh = self.model_mesh(model_index);
+            if mesh.primitives.is_empty() {
+                continue;
+            }
+
+            scene.instances.push(ExportInstance {
+                name: mesh.name.clone(),
+                mesh: Some(scene.meshes.len()),
+                transform: brush_transforms
+                    .get(&model_index)
+                    .copied()
+                    .unwrap_or(Mat4::IDENTITY),
+            });
+            scene.meshes.push(mesh);
+        }
+
+        let mut prop_meshes: HashMap<usize, Option<usize>> = HashMap::new();
+        for prop in &self.static_props {
+            let model_index = prop.model_index as usize;
+            let path = prop.model(&self.static_prop_models).unwrap_or_default();
+            let mesh = *prop_meshes.entry(model_index).or_insert_with(|| {
+                let mesh = props.as_mut()?.load_model(path)?;
+                scene.meshes.push(mesh);
+                Some(scene.meshes.len() - 1)
+            });
+
+            scene.instances.push(ExportInstance {
+                name: path.to_string(),
+                mesh,
+                transform: prop.transform(),
+            });
+        }
+
+        scene
+    }
+}
+
+/// Source is Z-up, OBJ and glTF are Y-up
+fn to_y_up(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, v.z, -v.y)
+}
+
+impl ExportScene {
+    pub fn write_obj<W: Write>(&self, mut writer: W) -> eyre::Result<()> {
+        // OBJ indices are global across the file
+        let mut vertex_base = 1;
+        for instance in &self.instances {
+            let Some(mesh) = instance.mesh.and_then(|m| self.meshes.get(m)) else {
+                continue;
+            };
+
+            let normal_matrix = instance.transform.inverse().transpose();
+            writeln!(writer, "o {}", instance.name)?;
+            for p in &mesh.positions {
+                let p = to_y_up(instance.transform.transform_point3(*p));
+                writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+            }
+            for n in &mesh.normals {
+                let n = to_y_up(normal_matrix.transform_vector3(*n).normalize_or_zero());
+                writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+            for uv in &mesh.uvs {
+                writeln!(writer, "vt {} {}", uv.x, 1.0 - uv.y)?;
+            }
+
+            for primitive in &mesh.primitives {
+                writeln!(writer, "usemtl {}", primitive.material)?;
+                for tri in primitive.indices.chunks_exact(3) {
+                    let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| i + vertex_base);
+                    writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+                }
+            }
+
+            vertex_base += mesh.positions.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a binary glTF (.glb) file. Materials only carry their name
+    pub fn write_glb<W: Write>(&self, mut writer: W) -> eyre::Result<()> {
+        let mut bin: Vec<u8> = vec![];
+        let mut buffer_views = vec![];
+        let mut accessors = vec![];
+        let mut materials: Vec<&str> = vec![];
+
+        // Returns the accessor index
+        let mut push_accessor = |bin: &mut Vec<u8>,
+                                 data: &[u8],
+                                 count: usize,
+                                 kind: &str,
+                                 component: u32,
+                                 extra: &str| {
+            while !bin.len().is_multiple_of(4) {
+                bin.push(0);
+            }
+            buffer_views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{}}}"#,
+                bin.len(),
+                data.len()
+            ));
+            bin.extend_from_slice(data);
+            accessors.push(format!(
+                    r#"{{"bufferView":{},"componentType":{component},"count":{count},"type":"{kind}"{extra}}}"#,
+                    buffer_views.len() - 1
+                ));
+            accessors.len() - 1
+        };
+
+        // Empty meshes can't be written, as glTF doesn't allow empty accessors or primitive lists
+        let mut mesh_indices = vec![];
+        let mut meshes_json = vec![];
+        for mesh in &self.meshes {
+            if mesh.positions.is_empty() || mesh.primitives.iter().all(|p| p.indices.is_empty()) {
+                mesh_indices.push(None);
+                continue;
+            }
+
+            let positions: Vec<Vec3> = mesh.positions.iter().map(|&p| to_y_up(p)).collect();
+            let normals: Vec<Vec3> = mesh.normals.iter().map(|&n| to_y_up(n)).collect();
+            ensure!(
+                normals.len() == positions.len() && mesh.uvs.len() == positions.len(),
+                "Mesh {} has mismatched attribute counts",
+                mesh.name
+            );
+
+            let (min, max) = positions
+                .iter()
+                .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &p| {
+                    (min.min(p), max.max(p))
+                });
+            let bounds = format!(
+                r#","min":[{},{},{}],"max":[{},{},{}]"#,
+                min.x, min.y, min.z, max.x, max.y, max.z
+            );
+
+            let position = push_accessor(
+                &mut bin,
+                &f32_bytes(positions.iter().flat_map(|p| p.to_array())),
+                positions.len(),
+                "VEC3",
+                GL_FLOAT,
+                &bounds,
+            );
+            let normal = push_accessor(
+                &mut bin,
+                &f32_bytes(normals.iter().flat_map(|n| n.to_array())),
+                normals.len(),
+                "VEC3",
+                GL_FLOAT,
+                "",
+            );
+            let uv = push_accessor(
+                &mut bin,
+                &f32_bytes(mesh.uvs.iter().flat_map(|uv| uv.to_array())),
+                mesh.uvs.len(),
+                "VEC2",
+                GL_FLOAT,
+                "",
+            );
+
+            let mut primitives = vec![];
+            for primitive in mesh.primitives.iter().filter(|p| !p.indices.is_empty()) {
+                let indices: Vec<u8> = primitive
+                    .indices
+                    .iter()
+                    .flat_map(|i| i.to_le_bytes())
+                    .collect();
+                let indices = push_accessor(
+                    &mut bin,
+                    &indices,
+                    primitive.indices.len(),
+                    "SCALAR",
+                    GL_UNSIGNED_INT,
+                    "",
+                );
+
+                let material = match materials.iter().position(|&m| m == primitive.material) {
+                    Some(i) => i,
+                    None => {
+                        materials.push(&primitive.material);
+                        materials.len() - 1
+                    }
+                };
+
+                primitives.push(format!(
+                    r#"{{"attributes":{{"POSITION":{position},"NORMAL":{normal},"TEXCOORD_0":{uv}}},"indices":{indices},"material":{material}}}"#
+                ));
+            }
+
+            mesh_indices.push(Some(meshes_json.len()));
+            meshes_json.push(format!(
+                r#"{{"name":{},"primitives":[{}]}}"#,
+                json_string(&mesh.name),
+                primitives.join(",")
+            ));
+        }
+
+        // Rotate the whole scene from Z-up to Y-up
+        let to_y_up = Mat4::from_cols(Vec4::X, Vec4::NEG_Z, Vec4::Y, Vec4::W);
+
+        let mut nodes_json = vec![];
+        for instance in &self.instances {
+            let matrix = to_y_up * instance.transform * to_y_up.inverse();
+            let mut node = format!(
+                r#"{{"name":{},"matrix":[{}]"#,
+                json_string(&instance.name),
+                matrix.to_cols_array().map(|v| v.to_string()).join(",")
+            );
+            if let Some(mesh) = instance
+                .mesh
+                .and_then(|m| mesh_indices.get(m).copied().flatten())
+            {
+                let _ = write!(node, r#","mesh":{mesh}"#);
+            }
+            node.push('}');
+            nodes_json.push(node);
+        }
+
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+
+        let materials_json: Vec<_> = materials
+            .iter()
+            .map(|m| format!(r#"{{"name":{}}}"#, json_string(m)))
+            .collect();
+
+        let mut json = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"powerjack"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],"meshes":[{}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+            (0..nodes_json.len())
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            nodes_json.join(","),
+            meshes_json.join(","),
+            materials_json.join(","),
+            accessors.join(","),
+            buffer_views.join(","),
+            bin.len()
+        );
+        while !json.len().is_multiple_of(4) {
+            json.push(' ');
+        }
+
+        let total_len = 12 + 8 + json.len() + 8 + bin.len();
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(total_len as u32).to_le_bytes())?;
+
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(json.as_bytes())?;
+
+        writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&bin)?;
+
+        Ok(())
+    }
+}
+
+const GL_FLOAT: u32 = 5126;
+const GL_UNSIGNED_INT: u32 = 5125;
+
+fn f32_bytes(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    values.flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lumps::{
+        BspDispCornerNeighbors, BspDispInfo, BspDispNeighbor, BspDispSubNeighbor, BspDispVert,
+        BspModel, BspTexData, BspTexInfo,
+    };
+
+    fn triangle(name: &str) -> ExportMesh {
+        ExportMesh {
+            name: name.to_string(),
+            positions: vec![Vec3::ZERO, Vec3::new(1.0, 2.0, 3.0), Vec3::X],
+            normals: vec![Vec3::Z; 3],
+            uvs: vec![Vec2::ZERO, Vec2::ONE, Vec2::X],
+            primitives: vec![ExportPrimitive {
+                material: "tools/wall".to_string(),
+                indices: vec![0, 1, 2],
+            }],
+        }
+    }
+
+    fn test_scene() -> ExportScene {
+        let instance = |name: &str, mesh, transform| ExportInstance {
+            name: name.to_string(),
+            mesh,
+            transform,
+        };
+
+        ExportScene {
+            meshes: vec![
+                triangle("world"),
+                ExportMesh {
+                    name: "*1".to_string(),
+                    ..Default::default()
+                },
+                triangle("prop"),
+            ],
+            instances: vec![
+                instance("world", Some(0), Mat4::IDENTITY),
+                instance("*1", Some(1), Mat4::IDENTITY),
+                instance("missing", None, Mat4::IDENTITY),
+                instance("prop", Some(2), Mat4::from_translation(Vec3::Z)),
+            ],
+        }
+    }
+
+    #[test]
+    fn obj_export() {
+        let mut out = vec![];
+        test_scene().write_obj(&mut out).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+
+        assert_eq!(lines.iter().filter(|l| l.starts_with("o ")).count(), 3);
+        assert!(lines.contains(&"v 1 3 -2"));
+        assert!(lines.contains(&"vn 0 1 -0"));
+        assert!(lines.contains(&"vt 1 0"));
+        assert!(lines.contains(&"usemtl tools/wall"));
+
+        // Indices continue across objects, and the prop is moved up
+        assert!(lines.contains(&"f 1/1/1 2/2/2 3/3/3"));
+        assert!(lines.contains(&"f 4/4/4 5/5/5 6/6/6"));
+        assert!(lines.contains(&"v 1 4 -2"));
+    }
+
+    #[test]
+    fn glb_export() {
+        let mut glb = vec![];
+        test_scene().write_glb(&mut glb).unwrap();
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(glb[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32_at(4), 2);
+        assert_eq!(u32_at(8) as usize, glb.len());
+
+        let json_len = u32_at(12) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        assert_eq!(json_len % 4, 0);
+        let json = std::str::from_utf8(&glb[20..20 + json_len]).unwrap();
+
+        let bin_len = u32_at(20 + json_len) as usize;
+        assert_eq!(&glb[24 + json_len..28 + json_len], b"BIN\0");
+        assert_eq!(28 + json_len + bin_len, glb.len());
+        assert!(json.contains(&format!(r#""buffers":[{{"byteLength":{bin_len}}}]"#)));
+
+        // The empty mesh is dropped and the prop's mesh index shifts down
+        assert!(json.contains(r#""meshes":[{"name":"world","#));
+        assert!(!json.contains(r#""name":"*1","primitives""#));
+        assert!(!json.contains(r#""count":0"#));
+        assert!(json.contains(r#""materials":[{"name":"tools/wall"}]"#));
+        assert!(
+            json.contains(r#""name":"prop","matrix":[1,0,0,0,0,1,0,0,0,0,1,0,0,1,0,1],"mesh":1}"#)
+        );
+        assert!(json.contains(r#"{"name":"*1","matrix":[1,0,0,0,0,1,0,0,0,0,1,0,0,0,0,1]}"#));
+    }
+
+    /// A 64 unit power 2 displacement rising 8 units every column, with its texture projected along x + z
+    fn displacement_world() -> Bsp {
+        let none = BspDispSubNeighbor {
+            neighbor: 0xFFFF,
+            neighbor_orientation: 0,
+            span: 0,
+            neighbor_span: 0,
+        };
+
+        Bsp {
+            vertices: vec![
+                [0.0, 0.0, 0.0],
+                [0.0, 64.0, 0.0],
+                [64.0, 64.0, 0.0],
+                [64.0, 0.0, 0.0],
+            ],
+            edges: vec![[0, 1], [1, 2], [2, 3], [3, 0]],
+            surfedges: (0..4).collect(),
+            faces: vec![BspFace {
+                plane_num: 0,
+                side: 0,
+                on_node: 0,
+                first_edge: 0,
+                num_edges: 4,
+                tex_info: 0,
+                disp_info: 0,
+                surface_fog_volume_id: -1,
+                styles: [255; 4],
+                lightmap_data_offset: -1,
+                area: 0.0,
+                lightmap_mins: [0, 0],
+                lightmap_size: [0, 0],
+                orig_face: 0,
+                num_primitives: 0,
+                first_primitive: 0,
+                smoothing_groups: 0,
+            }],
+            models: vec![BspModel {
+                mins: [0.0; 3],
+                maxs: [0.0; 3],
+                origin: [0.0; 3],
+                head_node: 0,
+                first_face: 0,
+                num_faces: 1,
+            }],
+            tex_info: vec![BspTexInfo {
+                texture_vecs: [[1.0, 0.0, 1.0, 0.0], [0.0, 1.0, 0.0, 0.0]],
+                lightmap_vecs: [[0.0; 4]; 2],
+                flags: BspSurfaceFlags::empty(),
+                tex_data: 0,
+            }],
+            tex_data: vec![BspTexData {
+                reflectivity: [0.0; 3],
+                name_index: 0,
+                width: 64,
+                height: 64,
+                view_width: 64,
+                view_height: 64,
+            }],
+            texdata_string_table: vec!["nature/blendgrass".to_string()],
+            disp_info: vec![BspDispInfo {
+                start_position: [0.0; 3],
+                disp_vert_start: 0,
+                disp_tri_start: -1,
+                power: 2,
+                min_tess: 0,
+                smoothing_angle: 0.0,
+                contents: Default::default(),
+                map_face: 0,
+                lightmap_alpha_start: 0,
+                lightmap_sample_position_start: 0,
+                edge_neighbors: [BspDispNeighbor {
+                    sub_neighbors: [none; 2],
+                }; 4],
+                corner_neighbors: [BspDispCornerNeighbors {
+                    neighbors: [0; 4],
+                    count: 0,
+                }; 4],
+                allowed_verts: [0; 10],
+            }],
+            disp_verts: (0..25)
+                .map(|i| BspDispVert {
+                    vec: [0.0, 0.0, 1.0],
+                    dist: (i % 5) as f32 * 8.0,
+                    alpha: 0.0,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn displacement_export() {
+        let bsp = displacement_world();
+        let disp = bsp.build_displacements().remove(0).unwrap();
+        let mesh = bsp.model_mesh(0);
+
+        assert_eq!(mesh.positions, disp.positions);
+        assert_eq!(mesh.normals, disp.normals);
+        assert_eq!(mesh.primitives[0].material, "nature/blendgrass");
+
+        // Displacement triangles are already counter-clockwise and must face along their normals
+        let indices = &mesh.primitives[0].indices;
+        assert_eq!(indices.len(), 32 * 3);
+        for t in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[t[i] as usize]);
+            let normal = (b - a).cross(c - a).normalize();
+            assert!(normal.dot(disp.normals[t[0] as usize]) > 0.9, "{t:?}");
+        }
+
+        // Texture coordinates come from the flat base face, not the raised surface
+        for (uv, base) in mesh.uvs.iter().zip(&disp.base_positions) {
+            assert_eq!(*uv, base.truncate() / 64.0);
+        }
+    }
+}
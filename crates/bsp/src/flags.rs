@@ -0,0 +1,123 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Brush, leaf and displacement contents (`CONTENTS_*`)
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct BspContents: u32 {
+        const SOLID = 0x1;
+        const WINDOW = 0x2;
+        const AUX = 0x4;
+        const GRATE = 0x8;
+        const SLIME = 0x10;
+        const WATER = 0x20;
+        const BLOCKLOS = 0x40;
+        const OPAQUE = 0x80;
+        const TESTFOGVOLUME = 0x100;
+        const UNUSED = 0x200;
+        const UNUSED6 = 0x400;
+        const TEAM1 = 0x800;
+        const TEAM2 = 0x1000;
+        const IGNORE_NODRAW_OPAQUE = 0x2000;
+        const MOVEABLE = 0x4000;
+        const AREAPORTAL = 0x8000;
+        const PLAYERCLIP = 0x10000;
+        const MONSTERCLIP = 0x20000;
+        const CURRENT_0 = 0x40000;
+        const CURRENT_90 = 0x80000;
+        const CURRENT_180 = 0x100000;
+        const CURRENT_270 = 0x200000;
+        const CURRENT_UP = 0x400000;
+        const CURRENT_DOWN = 0x800000;
+        const ORIGIN = 0x1000000;
+        const MONSTER = 0x2000000;
+        const DEBRIS = 0x4000000;
+        const DETAIL = 0x8000000;
+        const TRANSLUCENT = 0x10000000;
+        const LADDER = 0x20000000;
+        const HITBOX = 0x40000000;
+
+        // Trace masks (`MASK_*`)
+        const MASK_ALL = 0xFFFFFFFF;
+        const MASK_SOLID = Self::SOLID.bits() | Self::MOVEABLE.bits() | Self::WINDOW.bits() | Self::MONSTER.bits() | Self::GRATE.bits();
+        const MASK_PLAYERSOLID = Self::MASK_SOLID.bits() | Self::PLAYERCLIP.bits();
+        const MASK_NPCSOLID = Self::MASK_SOLID.bits() | Self::MONSTERCLIP.bits();
+        const MASK_WATER = Self::WATER.bits() | Self::MOVEABLE.bits() | Self::SLIME.bits();
+        const MASK_OPAQUE = Self::SOLID.bits() | Self::MOVEABLE.bits() | Self::OPAQUE.bits();
+        const MASK_VISIBLE = Self::MASK_OPAQUE.bits() | Self::IGNORE_NODRAW_OPAQUE.bits();
+        const MASK_SHOT = Self::SOLID.bits() | Self::MOVEABLE.bits() | Self::MONSTER.bits() | Self::WINDOW.bits() | Self::DEBRIS.bits() | Self::HITBOX.bits();
+        const MASK_SHOT_HULL = Self::SOLID.bits() | Self::MOVEABLE.bits() | Self::MONSTER.bits() | Self::WINDOW.bits() | Self::DEBRIS.bits() | Self::GRATE.bits();
+    }
+}
+
+bitflags! {
+    /// Texinfo surface flags (`SURF_*`)
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct BspSurfaceFlags: u32 {
+        const LIGHT = 0x1;
+        const SKY2D = 0x2;
+        const SKY = 0x4;
+        const WARP = 0x8;
+        const TRANS = 0x10;
+        const NOPORTAL = 0x20;
+        const TRIGGER = 0x40;
+        const NODRAW = 0x80;
+        const HINT = 0x100;
+        const SKIP = 0x200;
+        const NOLIGHT = 0x400;
+        const BUMPLIGHT = 0x800;
+        const NOSHADOWS = 0x1000;
+        const NODECALS = 0x2000;
+        const NOCHOP = 0x4000;
+        const HITBOX = 0x8000;
+    }
+}
+
+bitflags! {
+    /// Displacement triangle tags (`DISPTRI_TAG_*`)
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct BspDispTriTags: u16 {
+        const SURFACE = 0x1;
+        const WALKABLE = 0x2;
+        const BUILDABLE = 0x4;
+        const SURFPROP1 = 0x8;
+        const SURFPROP2 = 0x10;
+    }
+}
+
+bitflags! {
+    /// Static prop flags (`STATIC_PROP_*`)
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct StaticPropFlags: u32 {
+        const FADES = 0x1;
+        const USE_LIGHTING_ORIGIN = 0x2;
+        const NO_DRAW = 0x4;
+        const IGNORE_NORMALS = 0x8;
+        const NO_SHADOW = 0x10;
+        const SCREEN_SPACE_FADE = 0x20;
+        const NO_PER_VERTEX_LIGHTING = 0x40;
+        const NO_SELF_SHADOWING = 0x80;
+        const NO_PER_TEXEL_LIGHTING = 0x100;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_match_engine_values() {
+        assert_eq!(BspContents::MASK_SOLID.bits(), 0x200400B);
+        assert_eq!(BspContents::MASK_PLAYERSOLID.bits(), 0x201400B);
+        assert_eq!(BspContents::MASK_NPCSOLID.bits(), 0x202400B);
+        assert_eq!(BspContents::MASK_WATER.bits(), 0x4030);
+        assert_eq!(BspContents::MASK_OPAQUE.bits(), 0x4081);
+        assert_eq!(BspContents::MASK_VISIBLE.bits(), 0x6081);
+        assert_eq!(BspContents::MASK_SHOT.bits(), 0x46004003);
+        assert_eq!(BspContents::MASK_SHOT_HULL.bits(), 0x600400B);
+
+        // Unknown bits from newer games survive a round trip
+        let surface = BspSurfaceFlags::from_bits_retain(0x10084);
+        assert!(surface.contains(BspSurfaceFlags::SKY | BspSurfaceFlags::NODRAW));
+        assert_eq!(surface.bits(), 0x10084);
+    }
+}
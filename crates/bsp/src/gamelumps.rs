@@ -1,4 +1,9 @@
-use binrw::binread;
+use binrw::{binread, BinReaderExt, Endian};
+use eyre::ensure;
+use glam::{Mat4, Quat, Vec3};
+use std::io::{Cursor, Seek, SeekFrom};
+
+use crate::{flags::StaticPropFlags, lumps::BspColorRgbExp, profile::BspProfile};
 
 #[binread]
 #[derive(Debug, Clone)]
@@ -20,9 +25,10 @@ pub struct StaticPropLeafLump {
     pub leaf: Vec<u16>,
 }
 
+/// A single static prop. Fields that don't exist in older versions are filled with their defaults
 #[binread]
 #[derive(Debug, Clone)]
-// #[br(import(version: u16))]
+#[br(import(version: u16, tf2_layout: bool))]
 pub struct StaticPropLump {
     pub origin: [f32; 3],
     pub angles: [f32; 3],
@@ -31,45 +37,511 @@ pub struct StaticPropLump {
     pub first_leaf: u16,
     pub leaf_count: u16,
     pub solid: u8,
-    // #[br(if(!matches!(version, 7)))]
-    // pub flags_old: Option<u8>,
-
-    // pub skin: i32,
-    // pub fade_min_dist: f32,
-    // pub fade_max_dist: f32,
-    // pub lighting_origin: [f32; 3],
-
-    // #[br(if(version >= 5))]
-    // pub forced_fade_scale: f32,
-
-    // #[br(if(matches!(version, 6 | 7)))]
-    // pub min_dx_level: Option<u16>,
-    // #[br(if(matches!(version, 6 | 7)))]
-    // pub max_dx_level: Option<u16>,
-
-    // #[br(if(matches!(version, 7)))]
-    // pub flags: Option<u8>,
-    // #[br(if(matches!(version, 7)))]
-    // pub lightmap_res: [u16; 2],
-
-    // #[br(if(version >= 8))]
-    // pub min_cpu_level: Option<u8>,
-    // #[br(if(version >= 8))]
-    // pub max_cpu_level: Option<u8>,
-    // #[br(if(version >= 8))]
-    // pub min_gpu_level: Option<u8>,
-    // #[br(if(version >= 8))]
-    // pub max_gpu_level: Option<u8>,
-
-    // #[br(if(version >= 7))]
-    // pub diffuse_modulation: Option<u32>,
-
-    // #[br(if(matches!(version, 9 | 10), true), map(|b: u8| b != 0))]
-    // pub disable_x360: bool,
-
-    // #[br(if(version >= 10))]
-    // pub flags_ex: u32,
-
-    // #[br(if(version >= 11, 1.0))]
-    // pub uniform_scale: f32,
+    #[br(map = |f: u8| StaticPropFlags::from_bits_retain(f as u32))]
+    pub flags: StaticPropFlags,
+
+    pub skin: i32,
+    pub fade_min_dist: f32,
+    pub fade_max_dist: f32,
+    pub lighting_origin: [f32; 3],
+
+    #[br(if(version >= 5, 1.0))]
+    pub forced_fade_scale: f32,
+
+    #[br(if(matches!(version, 6 | 7) || tf2_layout))]
+    pub min_dx_level: Option<u16>,
+    #[br(if(matches!(version, 6 | 7) || tf2_layout))]
+    pub max_dx_level: Option<u16>,
+
+    #[br(if(version >= 8 && !tf2_layout))]
+    pub min_cpu_level: Option<u8>,
+    #[br(if(version >= 8 && !tf2_layout))]
+    pub max_cpu_level: Option<u8>,
+    #[br(if(version >= 8 && !tf2_layout))]
+    pub min_gpu_level: Option<u8>,
+    #[br(if(version >= 8 && !tf2_layout))]
+    pub max_gpu_level: Option<u8>,
+
+    /// RGBA tint
+    #[br(if(version >= 7 && !tf2_layout, [255; 4]))]
+    pub diffuse_modulation: [u8; 4],
+
+    /// Stored as a bool padded to 4 bytes
+    #[br(if(matches!(version, 9 | 10) && !tf2_layout), map(|b: u32| b != 0))]
+    pub disable_x360: bool,
+
+    /// On the TF2 layout this contains the full prop flags
+    #[br(if(version >= 10))]
+    pub flags_ex: u32,
+
+    #[br(if(tf2_layout))]
+    pub lightmap_resolution: Option<[u16; 2]>,
+
+    #[br(if(version >= 11, 1.0))]
+    pub uniform_scale: f32,
+}
+
+impl StaticPropLump {
+    /// Size in bytes of a prop for the given version
+    pub fn size(version: u16, tf2_layout: bool) -> usize {
+        if tf2_layout {
+            return 72;
+        }
+
+        let mut size = 56;
+        if version >= 5 {
+            size += 4;
+        }
+        if matches!(version, 6 | 7) {
+            size += 4;
+        }
+        if version >= 8 {
+            size += 4;
+        }
+        if version >= 7 {
+            size += 4;
+        }
+        if matches!(version, 9 | 10) {
+            size += 4;
+        }
+        if version >= 10 {
+            size += 4;
+        }
+        if version >= 11 {
+            size += 4;
+        }
+        size
+    }
+
+    /// World transform of the prop, angles are pitch/yaw/roll in degrees
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            Vec3::splat(self.uniform_scale),
+            angles_to_quat(self.angles),
+            self.origin.into(),
+        )
+    }
+
+    /// Looks up the model path in [`StaticPropGameLump::models`]
+    pub fn model<'a>(&self, models: &'a [String]) -> Option<&'a str> {
+        models.get(self.model_index as usize).map(|s| s.as_str())
+    }
+}
+
+/// Contents of the `sprp` game lump
+#[derive(Debug, Clone, Default)]
+pub struct StaticPropGameLump {
+    pub models: Vec<String>,
+    pub leafs: Vec<u16>,
+    pub props: Vec<StaticPropLump>,
+}
+
+impl StaticPropGameLump {
+    pub const ID: u32 = u32::from_be_bytes(*b"sprp");
+
+    pub fn parse(data: &[u8], version: u16, profile: &BspProfile) -> eyre::Result<Self> {
+        let endian = profile.endian;
+        let mut c = Cursor::new(data);
+        let models = c.read_type::<StaticPropDictLump>(endian)?.names;
+        let leafs = c.read_type::<StaticPropLeafLump>(endian)?.leaf;
+        let prop_count: u32 = c.read_type(endian)?;
+
+        let mut props = Vec::with_capacity(prop_count as usize);
+        if prop_count != 0 {
+            let remaining_bytes = data.len() - c.position() as usize;
+            let stride = remaining_bytes / prop_count as usize;
+
+            let tf2_layout = profile.static_prop_tf2_layout(version, stride);
+            let size = StaticPropLump::size(version, tf2_layout);
+            ensure!(
+                stride >= size,
+                "Static prop lump v{version} has {stride} bytes per prop, expected at least {size}"
+            );
+
+            // Some games append extra fields without bumping the version, so always seek by stride
+            let start_pos = c.position();
+            for i in 0..prop_count as u64 {
+                c.seek(SeekFrom::Start(start_pos + i * stride as u64))?;
+                props.push(c.read_type_args(endian, (version, tf2_layout))?);
+            }
+        }
+
+        Ok(Self {
+            models,
+            leafs,
+            props,
+        })
+    }
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy)]
+pub struct DetailSpriteLump {
+    /// Corners of the sprite quad, relative to the prop origin
+    pub upper_left: [f32; 2],
+    pub lower_right: [f32; 2],
+    /// Texture coordinates on the detail sprite sheet
+    pub tex_upper_left: [f32; 2],
+    pub tex_lower_right: [f32; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetailPropKind {
+    Model,
+    Sprite,
+    /// Sprite drawn as two intersecting quads
+    ShapeCross,
+    /// Sprite drawn as three quads
+    ShapeTri,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetailPropOrientation {
+    Normal,
+    ScreenAligned,
+    /// Rotates around the Z axis to face the viewer
+    ZAxis,
+    Unknown(u8),
+}
+
+#[binread]
+#[derive(Debug, Clone)]
+pub struct DetailPropLump {
+    pub origin: [f32; 3],
+    pub angles: [f32; 3],
+    /// Index into the model or sprite dictionary, depending on the kind
+    pub detail_model: u16,
+    pub leaf: u16,
+    pub lighting: BspColorRgbExp,
+    /// First entry in the detail prop lighting lumps
+    pub light_styles: u32,
+    pub light_style_count: u8,
+    pub sway_amount: u8,
+    pub shape_angle: u8,
+    pub shape_size: u8,
+    pub orientation: u8,
+    #[br(temp, pad_after = 2)]
+    _padding: u8,
+    pub kind: u8,
+    #[br(temp, pad_after = 2)]
+    _padding2: u8,
+    pub scale: f32,
+}
+
+impl DetailPropLump {
+    pub fn kind(&self) -> DetailPropKind {
+        match self.kind {
+            0 => DetailPropKind::Model,
+            1 => DetailPropKind::Sprite,
+            2 => DetailPropKind::ShapeCross,
+            3 => DetailPropKind::ShapeTri,
+            k => DetailPropKind::Unknown(k),
+        }
+    }
+
+    pub fn orientation(&self) -> DetailPropOrientation {
+        match self.orientation {
+            0 => DetailPropOrientation::Normal,
+            1 => DetailPropOrientation::ScreenAligned,
+            2 => DetailPropOrientation::ZAxis,
+            o => DetailPropOrientation::Unknown(o),
+        }
+    }
+
+    /// Returns the model path for model props
+    pub fn model<'a>(&self, models: &'a [String]) -> Option<&'a str> {
+        if self.kind() != DetailPropKind::Model {
+            return None;
+        }
+
+        models.get(self.detail_model as usize).map(|s| s.as_str())
+    }
+
+    /// Returns the sprite for sprite and shape props
+    pub fn sprite<'a>(&self, sprites: &'a [DetailSpriteLump]) -> Option<&'a DetailSpriteLump> {
+        if matches!(
+            self.kind(),
+            DetailPropKind::Model | DetailPropKind::Unknown(_)
+        ) {
+            return None;
+        }
+
+        sprites.get(self.detail_model as usize)
+    }
+}
+
+/// Per-prop light style sample, stored in the `dplt` (LDR) and `dplh` (HDR) game lumps
+#[binread]
+#[derive(Debug, Clone, Copy)]
+pub struct DetailPropLightStyleLump {
+    pub lighting: BspColorRgbExp,
+    pub style: u8,
+}
+
+/// Contents of the `dprp` game lump
+#[binread]
+#[derive(Debug, Clone, Default)]
+pub struct DetailPropGameLump {
+    #[br(temp)]
+    model_count: u32,
+    #[br(count = model_count, temp)]
+    models_raw: Vec<[u8; 128]>,
+    #[br(calc(models_raw.iter().map(|s| String::from_utf8_lossy(s).trim_end_matches('\0').to_string()).collect()))]
+    pub models: Vec<String>,
+
+    #[br(temp)]
+    sprite_count: u32,
+    #[br(count = sprite_count)]
+    pub sprites: Vec<DetailSpriteLump>,
+
+    #[br(temp)]
+    prop_count: u32,
+    #[br(count = prop_count)]
+    pub props: Vec<DetailPropLump>,
+}
+
+impl DetailPropGameLump {
+    pub const ID: u32 = u32::from_be_bytes(*b"dprp");
+    pub const LIGHTING_ID: u32 = u32::from_be_bytes(*b"dplt");
+    pub const LIGHTING_HDR_ID: u32 = u32::from_be_bytes(*b"dplh");
+}
+
+/// Parses a `dplt`/`dplh` game lump
+pub fn parse_detail_prop_lighting(
+    data: &[u8],
+    endian: Endian,
+) -> eyre::Result<Vec<DetailPropLightStyleLump>> {
+    let mut c = Cursor::new(data);
+    let count: u32 = c.read_type(endian)?;
+    let mut styles = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        styles.push(c.read_type(endian)?);
+    }
+
+    Ok(styles)
+}
+
+/// Converts Source pitch/yaw/roll angles in degrees to a rotation
+pub fn angles_to_quat(angles: [f32; 3]) -> Quat {
+    let pitch = Quat::from_axis_angle(Vec3::Y, angles[0].to_radians());
+    let yaw = Quat::from_axis_angle(Vec3::Z, angles[1].to_radians());
+    let roll = Quat::from_axis_angle(Vec3::X, angles[2].to_radians());
+    yaw * pitch * roll
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::BspGame;
+
+    /// Writes a prop with every field the version has, in file order
+    fn prop(version: u16, tf2_layout: bool, skin: i32) -> Vec<u8> {
+        let mut data = vec![];
+        for v in [1.0f32, 2.0, 3.0, 0.0, 90.0, 0.0] {
+            data.extend(v.to_le_bytes());
+        }
+        for v in [0u16, 0, 1] {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend([6, 1]);
+        data.extend(skin.to_le_bytes());
+        for v in [100.0f32, 200.0, 0.0, 0.0, 0.0] {
+            data.extend(v.to_le_bytes());
+        }
+
+        if version >= 5 {
+            data.extend(0.5f32.to_le_bytes());
+        }
+        if matches!(version, 6 | 7) || tf2_layout {
+            data.extend(70u16.to_le_bytes());
+            data.extend(95u16.to_le_bytes());
+        }
+        if version >= 8 && !tf2_layout {
+            data.extend([1, 2, 3, 4]);
+        }
+        if version >= 7 && !tf2_layout {
+            data.extend([255, 0, 0, 128]);
+        }
+        if matches!(version, 9 | 10) && !tf2_layout {
+            data.extend(1u32.to_le_bytes());
+        }
+        if version >= 10 {
+            data.extend(0x100u32.to_le_bytes());
+        }
+        if tf2_layout {
+            data.extend(32u16.to_le_bytes());
+            data.extend(16u16.to_le_bytes());
+        }
+        if version >= 11 {
+            data.extend(2.0f32.to_le_bytes());
+        }
+
+        assert_eq!(data.len(), StaticPropLump::size(version, tf2_layout));
+        data
+    }
+
+    /// A `sprp` lump with one model, one leaf and two props padded to `stride`
+    fn lump(version: u16, tf2_layout: bool, stride: usize) -> Vec<u8> {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        let mut name = b"models/props/crate.mdl".to_vec();
+        name.resize(128, 0);
+        data.extend(name);
+        data.extend(1u32.to_le_bytes());
+        data.extend(7u16.to_le_bytes());
+        data.extend(2u32.to_le_bytes());
+        for skin in [0, 1] {
+            let mut prop = prop(version, tf2_layout, skin);
+            prop.resize(stride, 0xAA);
+            data.extend(prop);
+        }
+        data
+    }
+
+    fn parse(version: u16, tf2_layout: bool, game: BspGame) -> StaticPropGameLump {
+        let stride = StaticPropLump::size(version, tf2_layout);
+        let profile = BspProfile::default().with_game(game);
+        StaticPropGameLump::parse(&lump(version, tf2_layout, stride), version, &profile).unwrap()
+    }
+
+    #[test]
+    fn static_prop_sizes() {
+        let sizes = [
+            (4, 56),
+            (5, 60),
+            (6, 64),
+            (7, 68),
+            (8, 68),
+            (9, 72),
+            (10, 76),
+            (11, 76),
+        ];
+        for (version, size) in sizes {
+            assert_eq!(StaticPropLump::size(version, false), size, "v{version}");
+        }
+        assert_eq!(StaticPropLump::size(10, true), 72);
+    }
+
+    #[test]
+    fn parse_static_prop_versions() {
+        for version in [4, 5, 6, 7, 10, 11] {
+            let lump = parse(version, false, BspGame::Generic);
+            assert_eq!(lump.models, ["models/props/crate.mdl"], "v{version}");
+            assert_eq!(lump.leafs, [7]);
+            assert_eq!(lump.props.len(), 2);
+
+            let prop = &lump.props[1];
+            assert_eq!(prop.origin, [1.0, 2.0, 3.0]);
+            assert_eq!(prop.skin, 1, "v{version}");
+            assert_eq!(prop.flags, StaticPropFlags::FADES);
+            assert_eq!(prop.fade_max_dist, 200.0);
+            assert_eq!(prop.model(&lump.models), Some("models/props/crate.mdl"));
+
+            let expected_scale = if version >= 5 { 0.5 } else { 1.0 };
+            assert_eq!(prop.forced_fade_scale, expected_scale);
+            let dx_level = matches!(version, 6 | 7).then_some(95);
+            assert_eq!(prop.max_dx_level, dx_level);
+            let tint = if version >= 7 {
+                [255, 0, 0, 128]
+            } else {
+                [255; 4]
+            };
+            assert_eq!(prop.diffuse_modulation, tint);
+            assert_eq!(prop.min_cpu_level, (version >= 8).then_some(1));
+            assert_eq!(prop.disable_x360, version == 10);
+            assert_eq!(prop.flags_ex, if version >= 10 { 0x100 } else { 0 });
+            assert_eq!(prop.lightmap_resolution, None);
+            assert_eq!(prop.uniform_scale, if version >= 11 { 2.0 } else { 1.0 });
+        }
+    }
+
+    #[test]
+    fn detect_tf2_static_props() {
+        // TF2's smaller v10 props are detected from the stride when the game isn't known
+        for game in [BspGame::Generic, BspGame::Tf2] {
+            let lump = parse(10, true, game);
+            let prop = &lump.props[1];
+            assert_eq!(prop.skin, 1);
+            assert_eq!(prop.max_dx_level, Some(95));
+            assert_eq!(prop.min_cpu_level, None);
+            assert_eq!(prop.diffuse_modulation, [255; 4]);
+            assert_eq!(prop.flags_ex, 0x100);
+            assert_eq!(prop.lightmap_resolution, Some([32, 16]));
+        }
+
+        // Extra per-prop data is skipped by seeking with the stride
+        let profile = BspProfile::default();
+        let padded = lump(7, false, 72);
+        let parsed = StaticPropGameLump::parse(&padded, 7, &profile).unwrap();
+        assert_eq!(parsed.props[1].skin, 1);
+        assert_eq!(parsed.props[1].diffuse_modulation, [255, 0, 0, 128]);
+
+        // Props smaller than the version requires are rejected
+        let short = lump(11, false, 72);
+        assert!(StaticPropGameLump::parse(&short, 11, &profile).is_err());
+    }
+
+    fn detail_prop(data: &mut Vec<u8>, detail_model: u16, kind: u8, orientation: u8) {
+        for v in [8.0f32, 16.0, 32.0, 0.0, 45.0, 0.0] {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend(detail_model.to_le_bytes());
+        data.extend(3u16.to_le_bytes());
+        // Lighting, first light style and style count
+        data.extend([64, 32, 16, 0]);
+        data.extend(0u32.to_le_bytes());
+        data.extend([1, 10, 0, 0, orientation, 0, 0, 0, kind, 0, 0, 0]);
+        data.extend(1.5f32.to_le_bytes());
+    }
+
+    #[test]
+    fn parse_detail_props() {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        let mut name = b"models/detail/grass.mdl".to_vec();
+        name.resize(128, 0);
+        data.extend(name);
+        data.extend(1u32.to_le_bytes());
+        for v in [-8.0f32, 16.0, 8.0, 0.0, 0.0, 0.0, 0.25, 0.5] {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend(2u32.to_le_bytes());
+        detail_prop(&mut data, 0, 0, 0);
+        detail_prop(&mut data, 0, 1, 2);
+
+        let lump: DetailPropGameLump = Cursor::new(&data).read_le().unwrap();
+        assert_eq!(lump.models, ["models/detail/grass.mdl"]);
+        assert_eq!(lump.sprites[0].lower_right, [8.0, 0.0]);
+        assert_eq!(lump.props.len(), 2);
+
+        let model = &lump.props[0];
+        assert_eq!(model.kind(), DetailPropKind::Model);
+        assert_eq!(model.origin, [8.0, 16.0, 32.0]);
+        assert_eq!(model.leaf, 3);
+        assert_eq!(model.sway_amount, 10);
+        assert_eq!(model.scale, 1.5);
+        assert_eq!(model.model(&lump.models), Some("models/detail/grass.mdl"));
+        assert!(model.sprite(&lump.sprites).is_none());
+
+        let sprite = &lump.props[1];
+        assert_eq!(sprite.kind(), DetailPropKind::Sprite);
+        assert_eq!(sprite.orientation(), DetailPropOrientation::ZAxis);
+        assert_eq!(sprite.model(&lump.models), None);
+        assert_eq!(
+            sprite.sprite(&lump.sprites).unwrap().tex_lower_right,
+            [0.25, 0.5]
+        );
+
+        let lighting =
+            parse_detail_prop_lighting(&[1, 0, 0, 0, 128, 64, 32, 1, 5], Endian::Little).unwrap();
+        assert_eq!(lighting.len(), 1);
+        assert_eq!(
+            (
+                lighting[0].lighting.r,
+                lighting[0].lighting.b,
+                lighting[0].lighting.exponent
+            ),
+            (128, 32, 1)
+        );
+        assert_eq!(lighting[0].style, 5);
+    }
 }
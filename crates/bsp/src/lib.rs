@@ -1,27 +1,214 @@
-use binrw::{BinRead, BinReaderExt, BinWriterExt, NullString};
-use eyre::{Context, OptionExt};
-use lumps::{BspColorRgbExp, BspFace, BspModel, BspPlane, BspTexData, BspTexInfo};
-use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use binrw::{BinRead, BinReaderExt, BinResult, BinWrite, BinWriterExt, Endian, NullString};
+use eyre::OptionExt;
+use lumps::{
+    BspArea, BspAreaPortal, BspBrush, BspBrushSide, BspColorRgbExp, BspCubemapSample, BspFace,
+    BspLeaf, BspLeafAmbientIndex, BspLeafAmbientLighting, BspModel, BspNode, BspOccluders,
+    BspOverlay, BspOverlayFade, BspPlane, BspPrimitive, BspPrimitiveKind, BspTexData, BspTexInfo,
+    BspWaterOverlay, BspWorldLight,
+};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+};
 
 use crate::{
-    gamelumps::{StaticPropDictLump, StaticPropLeafLump, StaticPropLump},
+    gamelumps::{
+        parse_detail_prop_lighting, DetailPropGameLump, DetailPropLightStyleLump, DetailPropLump,
+        DetailSpriteLump, StaticPropGameLump, StaticPropLump,
+    },
     lumps::{BspDispInfo, BspDispTri, BspDispVert, BspGameLump, BspGameLumpHeader},
+    physics::{parse_phys_collide, PhysModel},
+    profile::{BspLumpLayout, BspProfile},
+    visibility::BspVisibility,
 };
 
 pub const BSP_LUMP_COUNT: usize = 64;
 
+/// Names of the lumps as used by Source 2013, some lumps were repurposed by other branches
+pub const BSP_LUMP_NAMES: [&str; BSP_LUMP_COUNT] = [
+    "entities",
+    "planes",
+    "texdata",
+    "vertexes",
+    "visibility",
+    "nodes",
+    "texinfo",
+    "faces",
+    "lighting",
+    "occlusion",
+    "leafs",
+    "faceids",
+    "edges",
+    "surfedges",
+    "models",
+    "worldlights",
+    "leaffaces",
+    "leafbrushes",
+    "brushes",
+    "brushsides",
+    "areas",
+    "areaportals",
+    "portals",
+    "clusters",
+    "portalverts",
+    "clusterportals",
+    "dispinfo",
+    "originalfaces",
+    "physdisp",
+    "physcollide",
+    "vertnormals",
+    "vertnormalindices",
+    "disp_lightmap_alphas",
+    "disp_verts",
+    "disp_lightmap_sample_positions",
+    "game_lump",
+    "leafwaterdata",
+    "primitives",
+    "primverts",
+    "primindices",
+    "pakfile",
+    "clipportalverts",
+    "cubemaps",
+    "texdata_string_data",
+    "texdata_string_table",
+    "overlays",
+    "leafmindisttowater",
+    "face_macro_texture_info",
+    "disp_tris",
+    "physcollidesurface",
+    "wateroverlays",
+    "leaf_ambient_index_hdr",
+    "leaf_ambient_index",
+    "lighting_hdr",
+    "worldlights_hdr",
+    "leaf_ambient_lighting_hdr",
+    "leaf_ambient_lighting",
+    "xzippakfile",
+    "faces_hdr",
+    "map_flags",
+    "overlay_fades",
+    "overlay_system_levels",
+    "physlevel",
+    "disp_multiblend",
+];
+
+pub mod areas;
+pub mod diff;
+pub mod displacement;
+pub mod entities;
+pub mod export;
+pub mod flags;
 pub mod gamelumps;
+pub mod lighting;
+pub mod lumpfile;
 pub mod lumps;
+pub mod pakfile;
+pub mod physics;
+pub mod profile;
+pub mod slice;
+pub mod trace;
+pub mod validate;
+pub mod visibility;
+pub mod writer;
 
-#[derive(BinRead, Debug)]
-#[br(magic = b"VBSP")]
+#[derive(Debug)]
 pub struct BspHeader {
     pub version: i32,
-    #[br(count = BSP_LUMP_COUNT)]
     pub lumps: Vec<BspLump>,
+    pub map_revision: i32,
+    /// Console maps are big-endian, identified by a `PSBV` magic
+    pub endian: Endian,
+    pub lump_layout: BspLumpLayout,
 }
 
-#[derive(BinRead, Debug)]
+impl BinRead for BspHeader {
+    type Args<'a> = ();
+
+    /// The endianness is detected from the magic, `endian` is ignored
+    fn read_options<R: Read + Seek>(reader: &mut R, _endian: Endian, _args: ()) -> BinResult<Self> {
+        let pos = reader.stream_position()?;
+        let magic: [u8; 4] = reader.read_ne()?;
+        let endian = match &magic {
+            b"VBSP" => Endian::Little,
+            b"PSBV" => Endian::Big,
+            _ => {
+                return Err(binrw::Error::BadMagic {
+                    pos,
+                    found: Box::new(magic),
+                })
+            }
+        };
+
+        let version: i32 = reader.read_type(endian)?;
+        let mut entries = Vec::with_capacity(BSP_LUMP_COUNT);
+        let mut fourccs = Vec::with_capacity(BSP_LUMP_COUNT);
+        for _ in 0..BSP_LUMP_COUNT {
+            entries.push(reader.read_type::<[u32; 3]>(endian)?);
+            fourccs.push(reader.read_ne::<[u8; 4]>()?);
+        }
+        let map_revision = reader.read_type(endian)?;
+
+        let lump_layout = BspLumpLayout::detect(version, &entries);
+        let lumps = entries
+            .iter()
+            .zip(fourccs)
+            .map(|(&[a, b, c], fourcc)| match lump_layout {
+                BspLumpLayout::Standard => BspLump {
+                    offset: a,
+                    length: b,
+                    version: c as i32,
+                    fourcc,
+                },
+                BspLumpLayout::L4d2 => BspLump {
+                    offset: b,
+                    length: c,
+                    version: a as i32,
+                    fourcc,
+                },
+            })
+            .collect();
+
+        Ok(Self {
+            version,
+            lumps,
+            map_revision,
+            endian,
+            lump_layout,
+        })
+    }
+}
+
+impl BinWrite for BspHeader {
+    type Args<'a> = ();
+
+    /// Written with the endianness of the header, `endian` is ignored
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        _endian: Endian,
+        _args: (),
+    ) -> BinResult<()> {
+        let endian = self.endian;
+        writer.write_ne(match endian {
+            Endian::Little => b"VBSP",
+            Endian::Big => b"PSBV",
+        })?;
+        writer.write_type(&self.version, endian)?;
+        for lump in &self.lumps {
+            let fields = match self.lump_layout {
+                BspLumpLayout::Standard => [lump.offset, lump.length, lump.version as u32],
+                BspLumpLayout::L4d2 => [lump.version as u32, lump.offset, lump.length],
+            };
+            writer.write_type(&fields, endian)?;
+            writer.write_ne(&lump.fourcc)?;
+        }
+        writer.write_type(&self.map_revision, endian)?;
+        Ok(())
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
 pub struct BspLump {
     pub offset: u32,
     pub length: u32,
@@ -29,37 +216,42 @@ pub struct BspLump {
     pub fourcc: [u8; 4],
 }
 
+impl BspLump {
+    /// Compressed lumps store their uncompressed size in the fourcc, in the byte order of the header
+    pub fn uncompressed_length(&self, endian: Endian) -> Option<u32> {
+        let size = match endian {
+            Endian::Little => u32::from_le_bytes(self.fourcc),
+            Endian::Big => u32::from_be_bytes(self.fourcc),
+        };
+        Some(size).filter(|&s| s != 0)
+    }
+}
+
 pub struct BspFile<R: Read + Seek> {
     reader: R,
     pub header: BspHeader,
+    /// Lump data replaced by .lmp files, indexed by lump number
+    lump_overrides: HashMap<usize, Vec<u8>>,
 }
 
 impl<R: Read + Seek> BspFile<R> {
     pub fn new(mut reader: R) -> eyre::Result<Self> {
         let header = reader.read_le::<BspHeader>()?;
-        Ok(Self { reader, header })
+        Ok(Self {
+            reader,
+            header,
+            lump_overrides: HashMap::new(),
+        })
     }
 
     pub fn read_lump_raw_offset(&mut self, offset: u64, length: usize) -> eyre::Result<Vec<u8>> {
         self.reader.seek(SeekFrom::Start(offset))?;
         let id = self.reader.read_le::<[u8; 4]>()?;
         if length >= 4 && &id == b"LZMA" {
-            let actual_size = self.reader.read_le::<u32>()?;
-            let lzma_size = self.reader.read_le::<u32>()?;
-            let lzma_properties: [u8; 5] = self.reader.read_le()?;
-
-            let mut fixed_lump = Cursor::new(Vec::with_capacity(length));
-            fixed_lump.write_all(&lzma_properties)?;
-            fixed_lump.write_le(&(actual_size as u64))?;
-            let mut remaining_data = vec![0u8; lzma_size as usize];
-            self.reader.read_exact(&mut remaining_data)?;
-            fixed_lump.write_all(&remaining_data)?;
-
-            let mut decompressed_data = Vec::new();
-            fixed_lump.seek(SeekFrom::Start(0))?;
-            lzma_rs::lzma_decompress(&mut fixed_lump, &mut decompressed_data)?;
-
-            Ok(decompressed_data)
+            let header: LzmaHeader = self.reader.read_le()?;
+            let mut compressed = vec![0u8; header.lzma_size as usize];
+            self.reader.read_exact(&mut compressed)?;
+            decompress_lzma(&header, &compressed)
         } else {
             self.reader.seek(SeekFrom::Start(offset))?;
             let mut data = vec![0u8; length];
@@ -69,6 +261,10 @@ impl<R: Read + Seek> BspFile<R> {
     }
 
     pub fn read_lump_raw(&mut self, index: usize) -> eyre::Result<Vec<u8>> {
+        if let Some(data) = self.lump_overrides.get(&index) {
+            return Ok(data.clone());
+        }
+
         let lump = self
             .header
             .lumps
@@ -77,43 +273,123 @@ impl<R: Read + Seek> BspFile<R> {
 
         self.read_lump_raw_offset(lump.offset as u64, lump.length as usize)
     }
+}
+
+impl<R: Read + Seek> BspLumpReader for BspFile<R> {
+    fn header(&self) -> &BspHeader {
+        &self.header
+    }
+
+    fn lump_bytes(&mut self, index: usize) -> eyre::Result<Cow<'_, [u8]>> {
+        self.read_lump_raw(index).map(Cow::Owned)
+    }
+
+    fn lump_bytes_at(&mut self, offset: u64, length: usize) -> eyre::Result<Cow<'_, [u8]>> {
+        self.read_lump_raw_offset(offset, length).map(Cow::Owned)
+    }
+}
+
+/// Header of an LZMA compressed lump, following the "LZMA" id
+#[derive(BinRead, Debug)]
+pub(crate) struct LzmaHeader {
+    pub actual_size: u32,
+    pub lzma_size: u32,
+    pub properties: [u8; 5],
+}
+
+/// Decompresses Valve's LZMA lump format, which is a regular LZMA stream with a different header
+pub(crate) fn decompress_lzma(header: &LzmaHeader, compressed: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut fixed_lump = Cursor::new(Vec::with_capacity(compressed.len() + 13));
+    fixed_lump.write_all(&header.properties)?;
+    fixed_lump.write_le(&(header.actual_size as u64))?;
+    fixed_lump.write_all(compressed)?;
+
+    let mut decompressed_data = Vec::with_capacity(header.actual_size as usize);
+    fixed_lump.seek(SeekFrom::Start(0))?;
+    lzma_rs::lzma_decompress(&mut fixed_lump, &mut decompressed_data)?;
+
+    Ok(decompressed_data)
+}
+
+/// Source of decompressed lump data, either a streamed [`BspFile`] or an in-memory [`BspSlice`]
+pub trait BspLumpReader {
+    fn header(&self) -> &BspHeader;
 
-    pub fn read_lump_ex<'a, T>(&mut self, index: usize, max: usize) -> eyre::Result<Vec<T>>
+    /// Returns the decompressed contents of a lump
+    fn lump_bytes(&mut self, index: usize) -> eyre::Result<Cow<'_, [u8]>>;
+
+    /// Returns the decompressed data at an absolute file offset, used for game lumps
+    fn lump_bytes_at(&mut self, offset: u64, length: usize) -> eyre::Result<Cow<'_, [u8]>>;
+
+    fn read_lump_ex<'a, T>(&mut self, index: usize, max: usize) -> eyre::Result<Vec<T>>
     where
         T: BinRead,
         T::Args<'a>: Default,
     {
-        let data = self.read_lump_raw(index)?;
+        let endian = self.header().endian;
+        let data = self.lump_bytes(index)?;
         let mut cursor = Cursor::new(&data);
         let mut v = vec![];
         // TOOO(cohae): Might go wrong
         while cursor.position() < data.len() as u64 && v.len() < max {
-            v.push(cursor.read_le()?);
+            v.push(cursor.read_type(endian)?);
         }
 
         Ok(v)
     }
 
-    pub fn read_lump<'a, T>(&mut self, index: usize) -> eyre::Result<Vec<T>>
+    fn read_lump<'a, T>(&mut self, index: usize) -> eyre::Result<Vec<T>>
     where
         T: BinRead,
         T::Args<'a>: Default,
     {
         self.read_lump_ex(index, usize::MAX)
     }
-}
 
-#[derive(BinRead, Debug)]
-pub struct BspLumpHeader {
-    pub offset: u32,
-    pub length: u32,
-    pub version: i32,
-    pub fourcc: [u8; 4],
+    /// Reads a lump of versioned structs, passing `args` to every element
+    fn read_lump_args<'a, T>(&mut self, index: usize, args: T::Args<'a>) -> eyre::Result<Vec<T>>
+    where
+        T: BinRead,
+        T::Args<'a>: Clone,
+    {
+        let endian = self.header().endian;
+        let data = self.lump_bytes(index)?;
+        let mut cursor = Cursor::new(&data);
+        let mut v = vec![];
+        while cursor.position() < data.len() as u64 {
+            v.push(cursor.read_type_args(endian, args.clone())?);
+        }
+
+        Ok(v)
+    }
+
+    fn game_lumps(&mut self) -> eyre::Result<Vec<BspGameLump>> {
+        if self.header().lumps[35].length == 0 {
+            return Ok(vec![]);
+        }
+
+        let endian = self.header().endian;
+        let data = self.lump_bytes(35)?;
+        Ok(Cursor::new(&data)
+            .read_type::<BspGameLumpHeader>(endian)?
+            .lumps)
+    }
+
+    /// Returns the decompressed contents of a game lump
+    fn game_lump_bytes(&mut self, lump: &BspGameLump) -> eyre::Result<Cow<'_, [u8]>> {
+        self.lump_bytes_at(lump.fileofs as u64, lump.filelen as usize)
+    }
+
+    fn entities(&mut self) -> eyre::Result<String> {
+        let data = self.lump_bytes(0)?;
+        Ok(Cursor::new(&data).read_le::<NullString>()?.try_into()?)
+    }
 }
 
 /// Fully parsed BSP file
 ///
-/// NOTE: Due to it's size, the embedded pak file is not included in this struct. It can be obtained by calling [`BspFile::read_lump`] for lump 40
+/// NOTE: Due to it's size, the embedded pak file is not included in this struct. It can be obtained by calling [`BspFile::read_pakfile`]
+#[derive(Default)]
 pub struct Bsp {
     pub entities: String,
     pub planes: Vec<BspPlane>,
@@ -121,14 +397,53 @@ pub struct Bsp {
     pub edges: Vec<[u16; 2]>,
     pub surfedges: Vec<i32>,
     pub faces: Vec<BspFace>,
+    /// Faces with offsets into the HDR lighting lump, empty if they match `faces`
+    pub faces_hdr: Vec<BspFace>,
+    /// Referenced by [`BspFace::first_primitive`]
+    pub primitives: Vec<BspPrimitive>,
+    pub prim_verts: Vec<[f32; 3]>,
+    pub prim_indices: Vec<u16>,
     pub models: Vec<BspModel>,
+    pub nodes: Vec<BspNode>,
+    pub leafs: Vec<BspLeaf>,
+    /// Indices into `faces`, referenced by [`BspLeaf::first_leaf_face`]
+    pub leaf_faces: Vec<u16>,
+    /// Indexed like `leafs`
+    pub leaf_ambient_index: Vec<BspLeafAmbientIndex>,
+    pub leaf_ambient_index_hdr: Vec<BspLeafAmbientIndex>,
+    pub leaf_ambient_lighting: Vec<BspLeafAmbientLighting>,
+    pub leaf_ambient_lighting_hdr: Vec<BspLeafAmbientLighting>,
+    /// Indices into `brushes`, referenced by [`BspLeaf::first_leaf_brush`]
+    pub leaf_brushes: Vec<u16>,
+    pub brushes: Vec<BspBrush>,
+    pub brush_sides: Vec<BspBrushSide>,
+    /// `None` if the map has not been vis'd
+    pub visibility: Option<BspVisibility>,
+    /// Area 0 is unused, leafs outside of the world belong to it
+    pub areas: Vec<BspArea>,
+    pub area_portals: Vec<BspAreaPortal>,
+    /// Polygons of the area portals, referenced by [`BspAreaPortal::first_clip_portal_vert`]
+    pub clip_portal_verts: Vec<[f32; 3]>,
+    pub occluders: BspOccluders,
     pub tex_info: Vec<BspTexInfo>,
     pub tex_data: Vec<BspTexData>,
     pub lightmap_data: Vec<BspColorRgbExp>,
+    pub lightmap_data_hdr: Vec<BspColorRgbExp>,
 
     pub disp_info: Vec<BspDispInfo>,
     pub disp_verts: Vec<BspDispVert>,
     pub disp_tris: Vec<BspDispTri>,
+    pub cubemaps: Vec<BspCubemapSample>,
+    pub world_lights: Vec<BspWorldLight>,
+    pub world_lights_hdr: Vec<BspWorldLight>,
+    pub overlays: Vec<BspOverlay>,
+    /// Indexed like `overlays`
+    pub overlay_fades: Vec<BspOverlayFade>,
+    pub water_overlays: Vec<BspWaterOverlay>,
+    /// Collision models of the world and brush entities
+    pub phys_models: Vec<PhysModel>,
+    /// Why the physcollide lump couldn't be parsed, `phys_models` is empty if this is set
+    pub phys_collide_error: Option<String>,
 
     pub texdata_string_table: Vec<String>,
 
@@ -137,11 +452,30 @@ pub struct Bsp {
     pub static_prop_models: Vec<String>,
     pub static_prop_leafs: Vec<u16>,
     pub static_props: Vec<StaticPropLump>,
+
+    pub detail_prop_models: Vec<String>,
+    pub detail_prop_sprites: Vec<DetailSpriteLump>,
+    pub detail_props: Vec<DetailPropLump>,
+    pub detail_prop_lighting: Vec<DetailPropLightStyleLump>,
+    pub detail_prop_lighting_hdr: Vec<DetailPropLightStyleLump>,
+
+    /// Variant of the format the map was parsed with
+    pub profile: BspProfile,
 }
 
 impl Bsp {
-    pub fn parse<R: Read + Seek>(file: &mut BspFile<R>) -> eyre::Result<Self> {
-        let texdata_string_data = file.read_lump_raw(43)?;
+    /// Parses a map, using the variant detected from its header
+    pub fn parse(file: &mut impl BspLumpReader) -> eyre::Result<Self> {
+        let profile = BspProfile::detect(file.header());
+        Self::parse_with_profile(file, profile)
+    }
+
+    pub fn parse_with_profile(
+        file: &mut impl BspLumpReader,
+        profile: BspProfile,
+    ) -> eyre::Result<Self> {
+        let endian = profile.endian;
+        let texdata_string_data = file.lump_bytes(43)?.into_owned();
         let texdata_string_offsets: Vec<u32> = file.read_lump(44)?;
 
         let texdata_string_table = texdata_string_offsets
@@ -156,38 +490,62 @@ impl Bsp {
             })
             .collect();
 
-        let game_lumps = file.read_lump_ex::<BspGameLumpHeader>(35, 1)?[0]
-            .clone()
-            .lumps;
-
-        let mut static_prop_models = vec![];
-        let mut static_prop_leafs = vec![];
-        let mut static_props = vec![];
-
-        if let Some(sprp) = game_lumps.iter().find(|l| l.id == 0x73707270) {
-            let data = file.read_lump_raw_offset(sprp.fileofs as u64, sprp.filelen as usize)?;
-            let mut c = Cursor::new(&data);
-            static_prop_models = c.read_le::<StaticPropDictLump>()?.names;
-            static_prop_leafs = c.read_le::<StaticPropLeafLump>()?.leaf;
-            let prop_count: u32 = c.read_le()?;
-            if prop_count != 0 {
-                let remaining_bytes = data.len() - c.position() as usize;
-                let sprop_size = remaining_bytes / prop_count as usize;
-                let start_pos = c.position();
-                for i in 0..prop_count {
-                    c.seek(SeekFrom::Start(start_pos + i as u64 * sprop_size as u64))?;
-                    static_props.push(c.read_le()?);
-                }
+        let game_lumps = file.game_lumps()?;
+
+        let static_props = match game_lumps.iter().find(|l| l.id == StaticPropGameLump::ID) {
+            Some(sprp) => {
+                StaticPropGameLump::parse(&file.game_lump_bytes(sprp)?, sprp.version, &profile)?
             }
-        }
+            None => StaticPropGameLump::default(),
+        };
 
-        let entity_lump = file.read_lump_raw(0)?;
-        let entities = Cursor::new(entity_lump)
-            .read_le::<NullString>()?
-            .try_into()?;
+        let detail_props = match game_lumps.iter().find(|l| l.id == DetailPropGameLump::ID) {
+            Some(dprp) => Cursor::new(file.game_lump_bytes(dprp)?).read_type(endian)?,
+            None => DetailPropGameLump::default(),
+        };
+
+        let mut read_detail_lighting = |id: u32| -> eyre::Result<_> {
+            match game_lumps.iter().find(|l| l.id == id) {
+                Some(lump) => parse_detail_prop_lighting(&file.game_lump_bytes(lump)?, endian),
+                None => Ok(vec![]),
+            }
+        };
+        let detail_prop_lighting = read_detail_lighting(DetailPropGameLump::LIGHTING_ID)?;
+        let detail_prop_lighting_hdr = read_detail_lighting(DetailPropGameLump::LIGHTING_HDR_ID)?;
+
+        let entities = file.entities()?;
 
         // std::fs::write("entities.vdf", &entities)?;
 
+        let leaf_version = file.header().lumps[10].version;
+        let world_lights_version = file.header().lumps[15].version;
+        let world_lights_hdr_version = file.header().lumps[54].version;
+        let occluder_version = file.header().lumps[9].version;
+
+        let visibility_data = file.lump_bytes(4)?.into_owned();
+        let visibility = if visibility_data.is_empty() {
+            None
+        } else {
+            Some(BspVisibility::parse(visibility_data, endian)?)
+        };
+
+        let occluder_data = file.lump_bytes(9)?;
+        let occluders = if occluder_data.is_empty() {
+            BspOccluders::default()
+        } else {
+            Cursor::new(occluder_data).read_type_args(endian, (occluder_version,))?
+        };
+
+        // Collision data isn't needed to use the map, so a broken lump shouldn't fail the whole parse
+        let (phys_models, phys_collide_error) = match endian {
+            Endian::Little => match parse_phys_collide(&file.lump_bytes(29)?) {
+                Ok(models) => (models, None),
+                Err(e) => (vec![], Some(format!("{e:#}"))),
+            },
+            // TODO: Console maps store byte swapped IVP data, which isn't supported yet
+            Endian::Big => (vec![], None),
+        };
+
         Ok(Self {
             entities,
             planes: file.read_lump(1)?,
@@ -195,18 +553,122 @@ impl Bsp {
             edges: file.read_lump(12)?,
             surfedges: file.read_lump(13)?,
             faces: file.read_lump(7)?,
+            faces_hdr: file.read_lump(58)?,
+            primitives: file.read_lump(37)?,
+            prim_verts: file.read_lump(38)?,
+            prim_indices: file.read_lump(39)?,
             models: file.read_lump(14)?,
+            nodes: file.read_lump(5)?,
+            leafs: file.read_lump_args(10, (leaf_version,))?,
+            leaf_faces: file.read_lump(16)?,
+            leaf_ambient_index: file.read_lump(52)?,
+            leaf_ambient_index_hdr: file.read_lump(51)?,
+            leaf_ambient_lighting: file.read_lump(56)?,
+            leaf_ambient_lighting_hdr: file.read_lump(55)?,
+            leaf_brushes: file.read_lump(17)?,
+            brushes: file.read_lump(18)?,
+            brush_sides: file.read_lump(19)?,
+            visibility,
+            areas: file.read_lump(20)?,
+            area_portals: file.read_lump(21)?,
+            clip_portal_verts: file.read_lump(41)?,
+            occluders,
             tex_info: file.read_lump(6)?,
             tex_data: file.read_lump(2)?,
             lightmap_data: file.read_lump(8)?,
+            lightmap_data_hdr: file.read_lump(53)?,
             disp_info: file.read_lump(26)?,
             disp_verts: file.read_lump(33)?,
             disp_tris: file.read_lump(48)?,
+            cubemaps: file.read_lump(42)?,
+            world_lights: file.read_lump_args(15, (world_lights_version,))?,
+            world_lights_hdr: file.read_lump_args(54, (world_lights_hdr_version,))?,
+            overlays: file.read_lump(45)?,
+            overlay_fades: file.read_lump(60)?,
+            water_overlays: file.read_lump(50)?,
+            phys_models,
+            phys_collide_error,
             texdata_string_table,
             game_lumps,
-            static_prop_models,
-            static_prop_leafs,
-            static_props,
+            static_prop_models: static_props.models,
+            static_prop_leafs: static_props.leafs,
+            static_props: static_props.props,
+            detail_prop_models: detail_props.models,
+            detail_prop_sprites: detail_props.sprites,
+            detail_props: detail_props.props,
+            detail_prop_lighting,
+            detail_prop_lighting_hdr,
+            profile,
         })
     }
+
+    /// Returns the material name of a face, as stored in the texdata string table
+    pub fn face_material(&self, face: &BspFace) -> Option<&str> {
+        let ti = self.tex_info.get(face.tex_info as usize)?;
+        let td = self.tex_data.get(ti.tex_data as usize)?;
+        self.texdata_string_table
+            .get(td.name_index as usize)
+            .map(|s| s.as_str())
+    }
+
+    /// Returns the vertices of a face in winding order
+    pub fn face_vertices(&self, face: &BspFace) -> Vec<glam::Vec3> {
+        (0..face.num_edges.max(0) as usize)
+            .filter_map(|i| {
+                let edge = *self.surfedges.get(face.first_edge as usize + i)?;
+                let [v0, v1] = *self.edges.get(edge.unsigned_abs() as usize)?;
+                let v = if edge < 0 { v1 } else { v0 };
+                self.vertices.get(v as usize).map(|&v| v.into())
+            })
+            .collect()
+    }
+    /// Triangulates a face, returning indices into [`Bsp::face_vertices`] with Source's clockwise winding
+    ///
+    /// Faces that had their t-junctions fixed by VBSP use its triangulation, which avoids cracks. Other faces are triangulated as fans
+    pub fn face_triangles(&self, face: &BspFace) -> Vec<u32> {
+        let vertex_count = face.num_edges.max(0) as u32;
+        let mut indices = vec![];
+        for primitive in self
+            .primitives
+            .iter()
+            .skip(face.first_primitive as usize)
+            .take(face.primitive_count())
+        {
+            if primitive.vert_count != 0 {
+                continue;
+            }
+
+            let first = primitive.first_index as usize;
+            let Some(prim_indices) = self
+                .prim_indices
+                .get(first..first + primitive.index_count as usize)
+            else {
+                continue;
+            };
+
+            match primitive.kind() {
+                BspPrimitiveKind::TriList => {
+                    indices.extend(prim_indices.iter().map(|&i| i as u32));
+                }
+                BspPrimitiveKind::TriStrip => {
+                    for (k, t) in prim_indices.windows(3).enumerate() {
+                        let [a, b, c] = [t[0], t[1], t[2]].map(u32::from);
+                        // Every other triangle in a strip has its winding flipped
+                        if k % 2 == 0 {
+                            indices.extend([a, b, c]);
+                        } else {
+                            indices.extend([b, a, c]);
+                        }
+                    }
+                }
+                BspPrimitiveKind::Unknown(_) => {}
+            }
+        }
+
+        if indices.is_empty() || indices.iter().any(|&i| i >= vertex_count) {
+            return (2..vertex_count).flat_map(|i| [0, i - 1, i]).collect();
+        }
+
+        indices
+    }
 }
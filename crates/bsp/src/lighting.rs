@@ -0,0 +1,446 @@
+#[cfg(feature = "png")]
+use eyre::OptionExt;
+use glam::Vec3;
+
+use crate::{
+    flags::BspSurfaceFlags,
+    lumps::{BspColorRgbExp, BspCompressedLightCube, BspFace},
+    Bsp,
+};
+
+/// Faces can have up to 4 light styles, unused slots are set to this
+pub const LIGHT_STYLE_UNUSED: u8 = 255;
+
+/// Bumpmapped faces store a flat lightmap followed by one for each bump basis vector
+pub const NUM_BUMP_PAGES: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct BspFaceLightmap<'a> {
+    /// Size in luxels
+    pub width: usize,
+    pub height: usize,
+    pub bumped: bool,
+    pub styles: Vec<BspLightStylePage<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BspLightStylePage<'a> {
+    pub style: u8,
+    /// One page for flat lightmaps, or [`NUM_BUMP_PAGES`] for bumped ones, each `width * height` samples
+    pub pages: Vec<&'a [BspColorRgbExp]>,
+}
+
+/// Decoded linear RGB lightmap
+#[derive(Debug, Clone)]
+pub struct BspLightmapImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl BspLightmapImage {
+    pub fn to_srgb(&self) -> Vec<[u8; 3]> {
+        self.pixels.iter().map(|&p| linear_to_srgb(p)).collect()
+    }
+
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> eyre::Result<()> {
+        let data = self.to_srgb().into_iter().flatten().collect();
+        let image = image::RgbImage::from_raw(self.width as u32, self.height as u32, data)
+            .ok_or_eyre("Lightmap image has an invalid size")?;
+        image.save_with_format(path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BspLightmapAtlas {
+    pub image: BspLightmapImage,
+    /// Top-left corner of every face's lightmap in the atlas, indexed like [`Bsp::lightmap_faces`]
+    pub face_offsets: Vec<Option<[usize; 2]>>,
+}
+
+/// Ambient lighting from the 6 axis directions: +X, -X, +Y, -Y, +Z, -Z
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BspAmbientCube {
+    pub colors: [Vec3; 6],
+}
+
+impl BspAmbientCube {
+    pub fn from_compressed(cube: &BspCompressedLightCube) -> Self {
+        Self {
+            colors: cube.color.map(|c| Vec3::from(c.to_rgb())),
+        }
+    }
+
+    /// Returns the light arriving at a surface facing `normal`
+    pub fn sample(&self, normal: Vec3) -> Vec3 {
+        let n = normal.normalize_or_zero();
+        let sq = n * n;
+        let pick = |axis: usize, v: f32| self.colors[axis * 2 + usize::from(v < 0.0)];
+        pick(0, n.x) * sq.x + pick(1, n.y) * sq.y + pick(2, n.z) * sq.z
+    }
+}
+
+pub fn linear_to_srgb(rgb: [f32; 3]) -> [u8; 3] {
+    rgb.map(|c| {
+        let c = c.clamp(0.0, 1.0);
+        let s = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (s * 255.0).round() as u8
+    })
+}
+
+impl Bsp {
+    /// Whether the map was compiled with HDR lighting
+    pub fn has_hdr_lighting(&self) -> bool {
+        !self.lightmap_data_hdr.is_empty()
+    }
+
+    /// Returns the faces whose lightmap offsets point into the LDR or HDR lighting lump
+    ///
+    /// The HDR face lump is only written when it differs, otherwise the regular faces are used.
+    /// A HDR face lump that doesn't line up with the regular faces is ignored as well
+    pub fn lightmap_faces(&self, hdr: bool) -> &[BspFace] {
+        if hdr && self.faces_hdr.len() == self.faces.len() {
+            &self.faces_hdr
+        } else {
+            &self.faces
+        }
+    }
+
+    pub fn lightmap_samples(&self, hdr: bool) -> &[BspColorRgbExp] {
+        if hdr {
+            &self.lightmap_data_hdr
+        } else {
+            &self.lightmap_data
+        }
+    }
+
+    /// Whether the lightmaps of `face` contain bump pages
+    pub fn is_face_bumped(&self, face: &BspFace) -> bool {
+        self.tex_info
+            .get(face.tex_info as usize)
+            .is_some_and(|ti| ti.flags.contains(BspSurfaceFlags::BUMPLIGHT))
+    }
+
+    /// Returns every light style page of a face. Pages are laid out style-major, then by bump basis
+    ///
+    /// Returns `None` for unlit faces or when the lightmap data is out of bounds
+    pub fn face_lightmap(&self, face_index: usize, hdr: bool) -> Option<BspFaceLightmap<'_>> {
+        let face = self.lightmap_faces(hdr).get(face_index)?;
+        self.face_lightmap_pages(face, hdr)
+    }
+
+    /// Decodes the flat lightmap of the first light style of `face`
+    ///
+    /// `face` must come from [`Bsp::lightmap_faces`] with the same `hdr` value
+    pub fn lightmap_image(&self, face: &BspFace, hdr: bool) -> Option<BspLightmapImage> {
+        let lightmap = self.face_lightmap_pages(face, hdr)?;
+        let page = lightmap.styles.first()?.pages[0];
+        Some(BspLightmapImage {
+            width: lightmap.width,
+            height: lightmap.height,
+            pixels: page.iter().map(|c| c.to_rgb()).collect(),
+        })
+    }
+
+    /// Packs the lightmaps of all faces into a single image
+    pub fn lightmap_atlas(&self, hdr: bool) -> BspLightmapAtlas {
+        let faces = self.lightmap_faces(hdr);
+        let images: Vec<_> = faces.iter().map(|f| self.lightmap_image(f, hdr)).collect();
+
+        // Shelf packing, tallest images first
+        let mut order: Vec<usize> = (0..images.len()).filter(|&i| images[i].is_some()).collect();
+        order.sort_by_key(|&i| {
+            std::cmp::Reverse(images[i].as_ref().map(|img| img.height).unwrap_or(0))
+        });
+
+        let area: usize = images.iter().flatten().map(|i| i.width * i.height).sum();
+        let widest = images.iter().flatten().map(|i| i.width).max().unwrap_or(1);
+        let width = ((area as f32).sqrt().ceil() as usize)
+            .max(widest)
+            .next_power_of_two();
+
+        let mut offsets = vec![None; images.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in &order {
+            let img = images[i].as_ref().unwrap();
+            if x + img.width > width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+
+            offsets[i] = Some([x, y]);
+            x += img.width;
+            shelf_height = shelf_height.max(img.height);
+        }
+        let height = (y + shelf_height).max(1);
+
+        let mut pixels = vec![[0.0; 3]; width * height];
+        for (img, offset) in images.iter().zip(&offsets) {
+            let (Some(img), Some([ox, oy])) = (img, offset) else {
+                continue;
+            };
+
+            for row in 0..img.height {
+                let dst = (oy + row) * width + ox;
+                pixels[dst..dst + img.width]
+                    .copy_from_slice(&img.pixels[row * img.width..(row + 1) * img.width]);
+            }
+        }
+
+        BspLightmapAtlas {
+            image: BspLightmapImage {
+                width,
+                height,
+                pixels,
+            },
+            face_offsets: offsets,
+        }
+    }
+
+    /// Returns the ambient lighting at `point`, blended from the samples of the leaf containing it
+    ///
+    /// Returns `None` if the leaf doesn't have any ambient samples
+    pub fn ambient_cube_at(&self, point: Vec3, hdr: bool) -> Option<BspAmbientCube> {
+        let leaf_index = self.find_leaf(point);
+        let leaf = self.leafs.get(leaf_index)?;
+
+        // Version 0 leafs store a single cube inline
+        if let Some(cube) = &leaf.ambient_lighting {
+            return Some(BspAmbientCube::from_compressed(cube));
+        }
+
+        let (indices, samples) = if hdr && !self.leaf_ambient_index_hdr.is_empty() {
+            (
+                &self.leaf_ambient_index_hdr,
+                &self.leaf_ambient_lighting_hdr,
+            )
+        } else {
+            (&self.leaf_ambient_index, &self.leaf_ambient_lighting)
+        };
+
+        let index = indices.get(leaf_index)?;
+        let first = index.first_ambient_sample as usize;
+        let samples = samples.get(first..first + index.ambient_sample_count as usize)?;
+        if samples.is_empty() {
+            return None;
+        }
+
+        // Inverse squared distance weighting, like the engine does
+        let mins = Vec3::from(leaf.mins.map(|v| v as f32));
+        let size = Vec3::from(leaf.maxs.map(|v| v as f32)) - mins;
+        let mut total = BspAmbientCube::default();
+        let mut total_weight = 0.0;
+        for sample in samples {
+            let position = mins + size * Vec3::from(sample.position.map(|v| v as f32 / 255.0));
+            let weight = 1.0 / (position.distance_squared(point) + 1.0);
+            let cube = BspAmbientCube::from_compressed(&sample.cube);
+            for (t, c) in total.colors.iter_mut().zip(cube.colors) {
+                *t += c * weight;
+            }
+            total_weight += weight;
+        }
+
+        for c in &mut total.colors {
+            *c /= total_weight;
+        }
+
+        Some(total)
+    }
+
+    fn face_lightmap_pages(&self, face: &BspFace, hdr: bool) -> Option<BspFaceLightmap<'_>> {
+        if face.lightmap_data_offset < 0 {
+            return None;
+        }
+
+        let samples = self.lightmap_samples(hdr);
+        let width = face.lightmap_size[0] as usize + 1;
+        let height = face.lightmap_size[1] as usize + 1;
+        let page_size = width * height;
+        let bumped = self.is_face_bumped(face);
+        let page_count = if bumped { NUM_BUMP_PAGES } else { 1 };
+
+        let mut offset = face.lightmap_data_offset as usize / 4;
+        let mut styles = vec![];
+        for &style in face.styles.iter().take_while(|&&s| s != LIGHT_STYLE_UNUSED) {
+            let mut pages = Vec::with_capacity(page_count);
+            for _ in 0..page_count {
+                pages.push(samples.get(offset..offset + page_size)?);
+                offset += page_size;
+            }
+
+            styles.push(BspLightStylePage { style, pages });
+        }
+
+        Some(BspFaceLightmap {
+            width,
+            height,
+            bumped,
+            styles,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lumps::{BspLeaf, BspLeafAmbientIndex, BspLeafAmbientLighting};
+    use binrw::BinReaderExt;
+    use std::io::Cursor;
+
+    fn color(r: u8, g: u8, b: u8, exponent: i8) -> BspColorRgbExp {
+        BspColorRgbExp { r, g, b, exponent }
+    }
+
+    fn lit_face(lightmap_data_offset: i32, lightmap_size: [i32; 2]) -> BspFace {
+        BspFace {
+            plane_num: 0,
+            side: 0,
+            on_node: 0,
+            first_edge: 0,
+            num_edges: 0,
+            tex_info: -1,
+            disp_info: -1,
+            surface_fog_volume_id: -1,
+            styles: [
+                0,
+                LIGHT_STYLE_UNUSED,
+                LIGHT_STYLE_UNUSED,
+                LIGHT_STYLE_UNUSED,
+            ],
+            lightmap_data_offset,
+            area: 0.0,
+            lightmap_mins: [0, 0],
+            lightmap_size,
+            orig_face: 0,
+            num_primitives: 0,
+            first_primitive: 0,
+            smoothing_groups: 0,
+        }
+    }
+
+    #[test]
+    fn decode_colors() {
+        let decodes_to = |c: BspColorRgbExp, rgb: [f32; 3]| {
+            Vec3::from(c.to_rgb()).abs_diff_eq(Vec3::from(rgb), 1e-6)
+        };
+        assert!(decodes_to(color(255, 51, 0, 0), [1.0, 0.2, 0.0]));
+        assert!(decodes_to(color(255, 51, 102, 1), [2.0, 0.4, 0.8]));
+        assert!(decodes_to(color(102, 0, 255, -1), [0.2, 0.0, 0.5]));
+
+        // Overbright values are clamped without gamma correction
+        assert_eq!(color(200, 100, 10, 1).to_srgb(), [255, 200, 20]);
+
+        assert_eq!(linear_to_srgb([0.0, 1.0, 4.0]), [0, 255, 255]);
+        assert_eq!(linear_to_srgb([0.5, 0.001, -1.0]), [188, 3, 0]);
+    }
+
+    #[test]
+    fn pack_lightmap_atlas() {
+        let mut bsp = Bsp {
+            faces: vec![
+                lit_face(0, [1, 1]),
+                lit_face(16, [3, 0]),
+                lit_face(-1, [0, 0]),
+            ],
+            ..Default::default()
+        };
+        bsp.lightmap_data = (0..8).map(|i| color(i * 10, 0, 0, 0)).collect();
+
+        let atlas = bsp.lightmap_atlas(false);
+        assert_eq!(atlas.face_offsets, [Some([0, 0]), Some([0, 2]), None]);
+
+        // A 2x2 and a 4x1 lightmap end up on two shelves of a 4 luxel wide image
+        let image = &atlas.image;
+        assert_eq!((image.width, image.height), (4, 3));
+        let red = |x: usize, y: usize| (image.pixels[y * image.width + x][0] * 255.0).round();
+        assert_eq!(
+            [red(0, 0), red(1, 0), red(0, 1), red(1, 1)],
+            [0.0, 10.0, 20.0, 30.0]
+        );
+        assert_eq!([red(0, 2), red(3, 2)], [40.0, 70.0]);
+        assert_eq!(red(3, 0), 0.0);
+    }
+
+    /// A leaf spanning 0-255 on each axis, so ambient sample positions are in world units
+    fn leaf(version: i32, ambient: Option<[u8; 4]>) -> BspLeaf {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.extend([0; 4]);
+        data.extend(
+            [0i16; 3]
+                .iter()
+                .chain(&[255; 3])
+                .flat_map(|v| v.to_le_bytes()),
+        );
+        data.extend([0; 10]);
+        if let Some(color) = ambient {
+            data.extend(color.repeat(6));
+        }
+        data.extend([0; 2]);
+        Cursor::new(data).read_le_args((version,)).unwrap()
+    }
+
+    fn sample(rgbe: [u8; 4], position: [u8; 3]) -> BspLeafAmbientLighting {
+        let [r, g, b, exponent] = rgbe;
+        BspLeafAmbientLighting {
+            cube: BspCompressedLightCube {
+                color: [color(r, g, b, exponent as i8); 6],
+            },
+            position,
+        }
+    }
+
+    #[test]
+    fn ambient_lighting() {
+        let index = |first_ambient_sample, ambient_sample_count| BspLeafAmbientIndex {
+            ambient_sample_count,
+            first_ambient_sample,
+        };
+        let mut bsp = Bsp {
+            leafs: vec![leaf(1, None)],
+            leaf_ambient_index: vec![index(0, 2)],
+            leaf_ambient_lighting: vec![
+                sample([255, 0, 0, 0], [0, 0, 0]),
+                sample([0, 255, 0, 1], [10, 0, 0]),
+            ],
+            leaf_ambient_index_hdr: vec![index(0, 1)],
+            leaf_ambient_lighting_hdr: vec![sample([0, 0, 255, 0], [0, 0, 0])],
+            ..Default::default()
+        };
+
+        // Samples are weighted by their inverse squared distance, plus one
+        let cube = bsp.ambient_cube_at(Vec3::ZERO, false).unwrap();
+        let near_weight = 1.0;
+        let far_weight = 1.0 / 101.0;
+        let expected =
+            (Vec3::X * near_weight + Vec3::Y * 2.0 * far_weight) / (near_weight + far_weight);
+        assert!(cube.colors[0].abs_diff_eq(expected, 1e-6));
+        assert!(cube.sample(Vec3::NEG_Z).abs_diff_eq(expected, 1e-6));
+
+        let halfway = bsp
+            .ambient_cube_at(Vec3::new(5.0, 0.0, 0.0), false)
+            .unwrap();
+        assert!(halfway.colors[5].abs_diff_eq(Vec3::new(1.0, 2.0, 0.0) / 2.0, 1e-6));
+
+        assert_eq!(
+            bsp.ambient_cube_at(Vec3::ZERO, true).unwrap().colors[2],
+            Vec3::Z
+        );
+
+        bsp.leaf_ambient_index[0] = index(0, 0);
+        assert!(bsp.ambient_cube_at(Vec3::ZERO, false).is_none());
+
+        // Version 0 leafs carry a single cube, this one with an exponent of -1
+        bsp.leafs[0] = leaf(0, Some([0, 0, 255, 0xFF]));
+        assert_eq!(
+            bsp.ambient_cube_at(Vec3::ZERO, false).unwrap().colors[4],
+            Vec3::Z * 0.5
+        );
+    }
+}
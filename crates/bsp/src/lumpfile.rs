@@ -0,0 +1,256 @@
+use binrw::{BinRead, BinReaderExt, BinWrite, BinWriterExt};
+use eyre::{ensure, OptionExt};
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{writer::LUMP_GAME_LUMP, BspFile, BspHeader, BSP_LUMP_COUNT};
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct LumpFileHeader {
+    /// Offset of the lump data from the start of the .lmp file
+    pub lump_offset: i32,
+    pub lump_id: i32,
+    pub lump_version: i32,
+    pub lump_length: i32,
+    /// Must match the revision of the map for the override to be applied
+    pub map_revision: i32,
+}
+
+/// A `mapname_l_N.lmp` file, which replaces a single lump of a map
+#[derive(Debug, Clone)]
+pub struct LumpFile {
+    pub header: LumpFileHeader,
+    pub data: Vec<u8>,
+}
+
+impl LumpFile {
+    pub const HEADER_SIZE: i32 = 20;
+
+    pub fn new(lump_id: usize, lump_version: i32, map_revision: i32, data: Vec<u8>) -> Self {
+        Self {
+            header: LumpFileHeader {
+                lump_offset: Self::HEADER_SIZE,
+                lump_id: lump_id as i32,
+                lump_version,
+                lump_length: data.len() as i32,
+                map_revision,
+            },
+            data,
+        }
+    }
+
+    pub fn read<R: Read + Seek>(mut reader: R) -> eyre::Result<Self> {
+        let header: LumpFileHeader = reader.read_le()?;
+        ensure!(
+            (0..BSP_LUMP_COUNT as i32).contains(&header.lump_id),
+            "Invalid lump index {}",
+            header.lump_id
+        );
+
+        reader.seek(SeekFrom::Start(header.lump_offset.max(0) as u64))?;
+        let mut data = vec![0u8; header.lump_length.max(0) as usize];
+        reader.read_exact(&mut data)?;
+        Ok(Self { header, data })
+    }
+
+    pub fn write<W: Write + Seek>(&self, mut writer: W) -> eyre::Result<()> {
+        let header = LumpFileHeader {
+            lump_offset: Self::HEADER_SIZE,
+            lump_length: self.data.len() as i32,
+            ..self.header
+        };
+        writer.write_le(&header)?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    /// Returns the paths of the lump files belonging to `bsp_path`, in the order the engine applies them
+    ///
+    /// The engine stops at the first missing index, so `de_map_l_2.lmp` is ignored if `de_map_l_1.lmp` doesn't exist
+    pub fn discover(bsp_path: impl AsRef<Path>) -> Vec<PathBuf> {
+        let bsp_path = bsp_path.as_ref();
+        let Some(stem) = bsp_path.file_stem().and_then(|s| s.to_str()) else {
+            return vec![];
+        };
+
+        (0..)
+            .map(|i| bsp_path.with_file_name(format!("{stem}_l_{i}.lmp")))
+            .take_while(|p| p.is_file())
+            .collect()
+    }
+}
+
+/// Result of [`BspFile::apply_lump_files`]
+#[derive(Debug, Default)]
+pub struct AppliedLumpFiles {
+    pub applied: Vec<PathBuf>,
+    /// Files that were ignored, and why
+    pub skipped: Vec<(PathBuf, eyre::Report)>,
+}
+
+impl<R: Read + Seek> BspFile<R> {
+    /// Replaces a lump with the contents of a lump file. Overrides for another map revision are rejected
+    pub fn apply_lump_file(&mut self, lump_file: LumpFile) -> eyre::Result<()> {
+        let index = lump_file.header.lump_id as usize;
+        ensure!(index < BSP_LUMP_COUNT, "Invalid lump index {index}");
+        // Game lump directories contain absolute file offsets, which can't point into a .lmp file
+        ensure!(
+            index != LUMP_GAME_LUMP,
+            "Game lump overrides are not supported"
+        );
+        ensure!(
+            lump_file.header.map_revision == self.header.map_revision,
+            "Lump file is for map revision {}, map is revision {}",
+            lump_file.header.map_revision,
+            self.header.map_revision
+        );
+
+        let lump = &mut self.header.lumps[index];
+        lump.version = lump_file.header.lump_version;
+        lump.length = lump_file.data.len() as u32;
+        lump.fourcc = [0; 4];
+        self.lump_overrides.insert(index, lump_file.data);
+        Ok(())
+    }
+
+    /// Finds and applies the lump files next to `bsp_path`
+    ///
+    /// Like the engine, files that can't be applied (another map revision, game lump overrides) are skipped
+    pub fn apply_lump_files(&mut self, bsp_path: impl AsRef<Path>) -> AppliedLumpFiles {
+        let mut result = AppliedLumpFiles::default();
+        for path in LumpFile::discover(bsp_path) {
+            let applied = std::fs::File::open(&path)
+                .map_err(eyre::Report::from)
+                .and_then(|f| LumpFile::read(std::io::BufReader::new(f)))
+                .and_then(|lump_file| self.apply_lump_file(lump_file));
+
+            match applied {
+                Ok(()) => result.applied.push(path),
+                Err(e) => result.skipped.push((path, e)),
+            }
+        }
+
+        result
+    }
+
+    /// Whether `index` was replaced by a lump file
+    pub fn is_lump_overridden(&self, index: usize) -> bool {
+        self.lump_overrides.contains_key(&index)
+    }
+}
+
+/// Replaces the data of a single lump in an existing BSP file and updates its header entry
+///
+/// The data is written in place if it fits in the old lump, otherwise it is appended to the end of the file.
+/// Other lumps are never moved, so the absolute offsets in the game lump directory stay valid
+pub fn patch_lump<F: Read + Write + Seek>(
+    mut file: F,
+    index: usize,
+    version: i32,
+    data: &[u8],
+) -> eyre::Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut header: BspHeader = file.read_le()?;
+    let lump = header
+        .lumps
+        .get_mut(index)
+        .ok_or_eyre("Lump index out of bounds")?;
+
+    if data.len() > lump.length as usize {
+        let end = file.seek(SeekFrom::End(0))?;
+        let aligned = end.next_multiple_of(4);
+        file.write_all(&vec![0; (aligned - end) as usize])?;
+        lump.offset = aligned as u32;
+    }
+
+    file.seek(SeekFrom::Start(lump.offset as u64))?;
+    file.write_all(data)?;
+
+    lump.length = data.len() as u32;
+    lump.version = version;
+    lump.fourcc = [0; 4];
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_le(&header)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::{BspCompression, BspLumps};
+    use std::io::Cursor;
+
+    fn test_file() -> Vec<u8> {
+        let mut lumps = BspLumps::new(20, 3);
+        lumps.set_lump(0, b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec());
+        lumps.set_lump(1, vec![1; 32]);
+
+        let mut out = Cursor::new(vec![]);
+        lumps.write(&mut out, BspCompression::None).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn apply_override() {
+        let mut file = BspFile::new(Cursor::new(test_file())).unwrap();
+
+        let mut lmp = Cursor::new(vec![]);
+        LumpFile::new(0, 0, 3, b"{}\0".to_vec())
+            .write(&mut lmp)
+            .unwrap();
+        lmp.set_position(0);
+        file.apply_lump_file(LumpFile::read(lmp).unwrap()).unwrap();
+
+        assert!(file.is_lump_overridden(0));
+        assert_eq!(file.read_lump_raw(0).unwrap(), b"{}\0");
+        assert_eq!(file.read_lump_raw(1).unwrap(), vec![1; 32]);
+        assert!(file
+            .apply_lump_file(LumpFile::new(0, 0, 4, vec![]))
+            .is_err());
+    }
+
+    #[test]
+    fn skip_unusable_lump_files() {
+        let dir = std::env::temp_dir().join(format!("powerjack-lmp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bsp_path = dir.join("map.bsp");
+        let lump_files = [
+            LumpFile::new(1, 0, 3, vec![2; 8]),
+            // Stale override for another revision
+            LumpFile::new(0, 0, 2, b"{}\0".to_vec()),
+            LumpFile::new(LUMP_GAME_LUMP, 0, 3, vec![0; 4]),
+        ];
+        for (i, lump_file) in lump_files.iter().enumerate() {
+            let f = std::fs::File::create(dir.join(format!("map_l_{i}.lmp"))).unwrap();
+            lump_file.write(std::io::BufWriter::new(f)).unwrap();
+        }
+
+        let mut file = BspFile::new(Cursor::new(test_file())).unwrap();
+        let result = file.apply_lump_files(&bsp_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.applied, [dir.join("map_l_0.lmp")]);
+        assert_eq!(result.skipped.len(), 2);
+        assert_eq!(file.read_lump_raw(1).unwrap(), vec![2; 8]);
+        assert!(!file.is_lump_overridden(0));
+    }
+
+    #[test]
+    fn patch_in_place_and_append() {
+        let mut data = Cursor::new(test_file());
+        let original_len = data.get_ref().len();
+
+        patch_lump(&mut data, 1, 0, &[2; 16]).unwrap();
+        assert_eq!(data.get_ref().len(), original_len);
+        patch_lump(&mut data, 0, 0, &[3; 100]).unwrap();
+        assert!(data.get_ref().len() > original_len);
+
+        data.set_position(0);
+        let mut file = BspFile::new(data).unwrap();
+        assert_eq!(file.read_lump_raw(1).unwrap(), vec![2; 16]);
+        assert_eq!(file.read_lump_raw(0).unwrap(), vec![3; 100]);
+    }
+}
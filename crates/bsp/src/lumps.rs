@@ -1,6 +1,9 @@
-use binrw::{binread, BinRead};
+use binrw::{binrw, BinRead, BinWrite};
+use glam::{Vec2, Vec3};
 
-#[derive(BinRead, Debug, Clone)]
+use crate::flags::{BspContents, BspDispTriTags, BspSurfaceFlags};
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspFace {
     pub plane_num: u16,
     pub side: u8,
@@ -21,7 +24,43 @@ pub struct BspFace {
     pub smoothing_groups: u32,
 }
 
-#[derive(BinRead, Debug, Clone)]
+impl BspFace {
+    /// The top bit of `num_primitives` disables shadows on the face
+    pub fn primitive_count(&self) -> usize {
+        (self.num_primitives & 0x7FFF) as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BspPrimitiveKind {
+    TriList,
+    TriStrip,
+    Unknown(u8),
+}
+
+/// Triangulation of a face, written by VBSP when it fixes t-junctions
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspPrimitive {
+    #[brw(pad_after = 1)]
+    pub kind: u8,
+    pub first_index: u16,
+    pub index_count: u16,
+    /// Primitives with their own vertices aren't used by the engine
+    pub first_vert: u16,
+    pub vert_count: u16,
+}
+
+impl BspPrimitive {
+    pub fn kind(&self) -> BspPrimitiveKind {
+        match self.kind {
+            0 => BspPrimitiveKind::TriList,
+            1 => BspPrimitiveKind::TriStrip,
+            k => BspPrimitiveKind::Unknown(k),
+        }
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspModel {
     pub mins: [f32; 3],
     pub maxs: [f32; 3],
@@ -31,22 +70,91 @@ pub struct BspModel {
     pub num_faces: i32,
 }
 
-#[derive(BinRead, Debug, Clone)]
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspPlane {
     pub normal: [f32; 3],
     pub dist: f32,
     pub axis_type: i32,
 }
 
-#[derive(BinRead, Debug, Clone)]
+#[derive(BinRead, BinWrite, Debug, Clone)]
+pub struct BspNode {
+    pub plane_num: i32,
+    /// Negative values are leafs, encoded as `-(leaf + 1)`
+    pub children: [i32; 2],
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_face: u16,
+    pub num_faces: u16,
+    pub area: i16,
+    pub padding: i16,
+}
+
+#[binrw]
+#[derive(Debug, Clone, Default)]
+#[br(import(version: i32))]
+pub struct BspLeaf {
+    #[br(map = BspContents::from_bits_retain)]
+    #[bw(map = |c| c.bits())]
+    pub contents: BspContents,
+    pub cluster: i16,
+    /// Packed area (9 bits) and flags (7 bits)
+    pub area_flags: u16,
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_leaf_face: u16,
+    pub num_leaf_faces: u16,
+    pub first_leaf_brush: u16,
+    pub num_leaf_brushes: u16,
+    pub leaf_water_data_id: i16,
+    /// Only present in version 0 leafs, newer maps store these in a separate lump
+    #[br(if(version == 0))]
+    pub ambient_lighting: Option<BspCompressedLightCube>,
+    #[br(temp)]
+    #[bw(calc = 0)]
+    _padding: i16,
+}
+
+impl BspLeaf {
+    pub fn area(&self) -> u16 {
+        self.area_flags & 0x1FF
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.area_flags >> 9
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
+pub struct BspBrush {
+    pub first_side: i32,
+    pub num_sides: i32,
+    #[br(map = BspContents::from_bits_retain)]
+    #[bw(map = |c| c.bits())]
+    pub contents: BspContents,
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
+pub struct BspBrushSide {
+    pub plane_num: u16,
+    pub tex_info: i16,
+    pub disp_info: i16,
+    /// Bevel planes are only used for box traces
+    pub bevel: u8,
+    pub thin: u8,
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspTexInfo {
     pub texture_vecs: [[f32; 4]; 2],
     pub lightmap_vecs: [[f32; 4]; 2],
-    pub flags: u32,
+    #[br(map = BspSurfaceFlags::from_bits_retain)]
+    #[bw(map = |f| f.bits())]
+    pub flags: BspSurfaceFlags,
     pub tex_data: i32,
 }
 
-#[derive(BinRead, Debug, Clone)]
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspTexData {
     pub reflectivity: [f32; 3],
     /// Index into texdata_string_table
@@ -57,7 +165,7 @@ pub struct BspTexData {
     pub view_height: i32,
 }
 
-#[derive(BinRead, Clone, Copy, Debug)]
+#[derive(BinRead, BinWrite, Clone, Copy, Debug)]
 pub struct BspColorRgbExp {
     pub r: u8,
     pub g: u8,
@@ -66,25 +174,43 @@ pub struct BspColorRgbExp {
 }
 
 impl BspColorRgbExp {
+    /// Scales the linear color to 0-255 and clamps overbright values. No gamma correction is
+    /// applied, use [`crate::lighting::linear_to_srgb`] for that
     pub fn to_srgb(&self) -> [u8; 3] {
-        let rgb = self.to_rgb();
-        [
-            (rgb[0] * 255.0) as u8,
-            (rgb[1] * 255.0) as u8,
-            (rgb[2] * 255.0) as u8,
-        ]
+        self.to_rgb().map(|c| (c * 255.0) as u8)
     }
 
+    /// Decodes the color to linear RGB, where 1.0 is full brightness
     pub fn to_rgb(&self) -> [f32; 3] {
+        let scale = 2f32.powi(self.exponent as i32) / 255.0;
         [
-            (self.r as f32 / 255.0) * 2f32.powi(self.exponent as i32),
-            (self.b as f32 / 255.0) * 2f32.powi(self.exponent as i32),
-            (self.g as f32 / 255.0) * 2f32.powi(self.exponent as i32),
+            self.r as f32 * scale,
+            self.g as f32 * scale,
+            self.b as f32 * scale,
         ]
     }
 }
 
-#[derive(BinRead, Debug, Clone)]
+#[derive(BinRead, BinWrite, Clone, Copy, Debug)]
+pub struct BspCompressedLightCube {
+    pub color: [BspColorRgbExp; 6],
+}
+
+#[derive(BinRead, BinWrite, Clone, Copy, Debug)]
+pub struct BspLeafAmbientIndex {
+    pub ambient_sample_count: u16,
+    pub first_ambient_sample: u16,
+}
+
+#[derive(BinRead, BinWrite, Clone, Copy, Debug)]
+pub struct BspLeafAmbientLighting {
+    pub cube: BspCompressedLightCube,
+    /// Position within the leaf bounds, 0-255 on each axis
+    #[brw(pad_after = 1)]
+    pub position: [u8; 3],
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspDispInfo {
     pub start_position: [f32; 3],
     pub disp_vert_start: i32,
@@ -92,36 +218,81 @@ pub struct BspDispInfo {
     pub power: i32,
     pub min_tess: i32,
     pub smoothing_angle: f32,
-    pub contents: i32,
+    #[br(map = BspContents::from_bits_retain)]
+    #[bw(map = |c| c.bits())]
+    pub contents: BspContents,
     pub map_face: u16,
+    #[brw(pad_before = 2)]
     pub lightmap_alpha_start: i32,
     pub lightmap_sample_position_start: i32,
-    pub neighbor_data: [u8; 90],
+    /// Indexed by edge: left, top, right, bottom
+    pub edge_neighbors: [BspDispNeighbor; 4],
+    /// Indexed by corner: lower left, upper left, upper right, lower right
+    pub corner_neighbors: [BspDispCornerNeighbors; 4],
     pub allowed_verts: [u32; 10],
 }
 
-#[derive(BinRead, Debug, Clone)]
+/// Each displacement edge can touch up to two neighbors, one for each half
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspDispNeighbor {
+    pub sub_neighbors: [BspDispSubNeighbor; 2],
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspDispSubNeighbor {
+    /// Index into the dispinfo lump, 0xFFFF if there is no neighbor
+    pub neighbor: u16,
+    /// Rotation of the neighbor relative to this displacement, in 90 degree steps
+    pub neighbor_orientation: u8,
+    pub span: u8,
+    #[brw(pad_after = 1)]
+    pub neighbor_span: u8,
+}
+
+impl BspDispSubNeighbor {
+    pub fn neighbor(&self) -> Option<usize> {
+        (self.neighbor != 0xFFFF).then_some(self.neighbor as usize)
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspDispCornerNeighbors {
+    pub neighbors: [u16; 4],
+    #[brw(pad_after = 1)]
+    pub count: u8,
+}
+
+impl BspDispCornerNeighbors {
+    pub fn neighbors(&self) -> &[u16] {
+        &self.neighbors[..(self.count as usize).min(4)]
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspDispVert {
     pub vec: [f32; 3],
     pub dist: f32,
     pub alpha: f32,
 }
 
-#[derive(BinRead, Debug, Clone)]
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct BspDispTri {
-    pub tags: u16,
+    #[br(map = BspDispTriTags::from_bits_retain)]
+    #[bw(map = |t| t.bits())]
+    pub tags: BspDispTriTags,
 }
 
-#[binread]
+#[binrw]
 #[derive(Debug, Clone)]
 pub struct BspGameLumpHeader {
     #[br(temp)]
+    #[bw(calc = lumps.len() as u32)]
     count: u32,
     #[br(count = count)]
     pub lumps: Vec<BspGameLump>,
 }
 
-#[derive(BinRead, Clone, Copy, Debug)]
+#[derive(BinRead, BinWrite, Clone, Copy, Debug)]
 pub struct BspGameLump {
     pub id: u32,
     pub flags: u16,
@@ -129,3 +300,284 @@ pub struct BspGameLump {
     pub fileofs: u32,
     pub filelen: u32,
 }
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspArea {
+    pub num_area_portals: i32,
+    pub first_area_portal: i32,
+}
+
+/// One side of a portal between two areas, every portal is stored once for each area it connects
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspAreaPortal {
+    /// Shared by both sides of the portal, matches the `portalnumber` of its `func_areaportal`
+    pub portal_key: u16,
+    pub other_area: u16,
+    pub first_clip_portal_vert: u16,
+    pub clip_portal_verts: u16,
+    pub plane_num: i32,
+}
+
+#[binrw]
+#[derive(Debug, Clone, Default)]
+#[br(import(version: i32))]
+pub struct BspOccluders {
+    #[br(temp)]
+    #[bw(calc = occluders.len() as i32)]
+    occluder_count: i32,
+    #[br(count = occluder_count.max(0), args { inner: (version,) })]
+    pub occluders: Vec<BspOccluder>,
+    #[br(temp)]
+    #[bw(calc = polys.len() as i32)]
+    poly_count: i32,
+    #[br(count = poly_count.max(0))]
+    pub polys: Vec<BspOccluderPoly>,
+    #[br(temp)]
+    #[bw(calc = vertex_indices.len() as i32)]
+    vertex_index_count: i32,
+    /// Indices into the vertex lump
+    #[br(count = vertex_index_count.max(0))]
+    pub vertex_indices: Vec<i32>,
+}
+
+/// A `func_occluder`
+#[binrw]
+#[derive(Debug, Clone)]
+#[br(import(version: i32))]
+pub struct BspOccluder {
+    pub flags: i32,
+    pub first_poly: i32,
+    pub poly_count: i32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    /// Only present in version 2 and up, version 1 uses `doccluderdataV1_t`
+    #[br(if(version >= 2))]
+    pub area: Option<i32>,
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspOccluderPoly {
+    pub first_vertex_index: i32,
+    pub vertex_count: i32,
+    pub plane_num: i32,
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspCubemapSample {
+    pub origin: [i32; 3],
+    /// Cubemap resolution as a power of two, 0 uses the default size
+    pub size: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BspEmitType {
+    Surface,
+    Point,
+    Spotlight,
+    Skylight,
+    QuakeLight,
+    SkyAmbient,
+    Unknown(i32),
+}
+
+/// Light used by the engine to light models and props
+#[derive(BinRead, BinWrite, Debug, Clone)]
+#[br(import(version: i32))]
+pub struct BspWorldLight {
+    pub origin: [f32; 3],
+    pub intensity: [f32; 3],
+    pub normal: [f32; 3],
+    /// Only present in lump version 1 and later
+    #[br(if(version >= 1))]
+    pub shadow_cast_offset: Option<[f32; 3]>,
+    pub cluster: i32,
+    pub emit_type: i32,
+    pub style: i32,
+    /// Cosines of the inner and outer spotlight cone angles
+    pub stopdot: f32,
+    pub stopdot2: f32,
+    pub exponent: f32,
+    pub radius: f32,
+    pub constant_attn: f32,
+    pub linear_attn: f32,
+    pub quadratic_attn: f32,
+    pub flags: i32,
+    pub tex_info: i32,
+    pub owner: i32,
+}
+
+impl BspWorldLight {
+    pub fn emit_type(&self) -> BspEmitType {
+        match self.emit_type {
+            0 => BspEmitType::Surface,
+            1 => BspEmitType::Point,
+            2 => BspEmitType::Spotlight,
+            3 => BspEmitType::Skylight,
+            4 => BspEmitType::QuakeLight,
+            5 => BspEmitType::SkyAmbient,
+            t => BspEmitType::Unknown(t),
+        }
+    }
+}
+
+/// Info decal projected onto a set of faces. Water overlays use the same layout with more faces
+#[derive(BinRead, BinWrite, Debug, Clone)]
+pub struct BspOverlayLump<const FACES: usize> {
+    pub id: i32,
+    pub tex_info: i16,
+    /// Face count in the low 14 bits, render order in the high 2
+    pub face_count_and_render_order: u16,
+    pub faces: [i32; FACES],
+    pub u: [f32; 2],
+    pub v: [f32; 2],
+    /// Corners in the overlay's basis. The Z components hold the U basis vector and a flip flag
+    pub uv_points: [[f32; 3]; 4],
+    pub origin: [f32; 3],
+    pub basis_normal: [f32; 3],
+}
+
+pub type BspOverlay = BspOverlayLump<64>;
+pub type BspWaterOverlay = BspOverlayLump<256>;
+
+impl<const FACES: usize> BspOverlayLump<FACES> {
+    pub fn face_count(&self) -> usize {
+        (self.face_count_and_render_order & 0x3FFF) as usize
+    }
+
+    pub fn render_order(&self) -> u16 {
+        self.face_count_and_render_order >> 14
+    }
+
+    pub fn faces(&self) -> &[i32] {
+        &self.faces[..self.face_count().min(FACES)]
+    }
+
+    /// Returns the U, V and normal basis vectors
+    pub fn basis(&self) -> [Vec3; 3] {
+        let u = Vec3::new(
+            self.uv_points[0][2],
+            self.uv_points[1][2],
+            self.uv_points[2][2],
+        );
+        let normal = Vec3::from(self.basis_normal);
+        let flip = self.uv_points[3][2] == 1.0;
+        let v = if flip {
+            -normal.cross(u)
+        } else {
+            normal.cross(u)
+        };
+
+        [u, v, normal]
+    }
+
+    /// World space corners of the overlay quad
+    pub fn corners(&self) -> [Vec3; 4] {
+        let [u, v, _] = self.basis();
+        let origin = Vec3::from(self.origin);
+        self.uv_points.map(|p| origin + u * p[0] + v * p[1])
+    }
+
+    /// Texture coordinates for each of [`Self::corners`]
+    pub fn tex_coords(&self) -> [Vec2; 4] {
+        [
+            Vec2::new(self.u[0], self.v[0]),
+            Vec2::new(self.u[0], self.v[1]),
+            Vec2::new(self.u[1], self.v[1]),
+            Vec2::new(self.u[1], self.v[0]),
+        ]
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+pub struct BspOverlayFade {
+    /// Squared fade distances
+    pub min_dist_sq: f32,
+    pub max_dist_sq: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binrw::BinReaderExt;
+    use std::io::Cursor;
+
+    fn bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parse_cubemap_sample() {
+        let mut data = vec![];
+        for v in [-64i32, 128, 32, 6] {
+            data.extend(v.to_le_bytes());
+        }
+        assert_eq!(data.len(), 16);
+
+        let cubemap: BspCubemapSample = Cursor::new(data).read_le().unwrap();
+        assert_eq!(cubemap.origin, [-64, 128, 32]);
+        assert_eq!(cubemap.size, 6);
+    }
+
+    #[test]
+    fn parse_world_light_versions() {
+        for version in [0, 1] {
+            let mut data = bytes(&[0.0, 0.0, 128.0, 100.0, 50.0, 25.0, 0.0, 0.0, -1.0]);
+            if version >= 1 {
+                data.extend(bytes(&[0.0, 0.0, 4.0]));
+            }
+            for v in [3i32, 2, 0] {
+                data.extend(v.to_le_bytes());
+            }
+            data.extend(bytes(&[0.9, 0.7, 1.0, 512.0, 0.0, 0.0, 1.0]));
+            for v in [1i32, -1, 0] {
+                data.extend(v.to_le_bytes());
+            }
+            assert_eq!(data.len(), if version >= 1 { 100 } else { 88 });
+
+            let light: BspWorldLight = Cursor::new(data).read_le_args((version,)).unwrap();
+            assert_eq!(light.intensity, [100.0, 50.0, 25.0]);
+            assert_eq!(
+                light.shadow_cast_offset,
+                (version >= 1).then_some([0.0, 0.0, 4.0])
+            );
+            assert_eq!(light.cluster, 3);
+            assert_eq!(light.emit_type(), BspEmitType::Spotlight);
+            assert_eq!(light.stopdot2, 0.7);
+            assert_eq!(light.quadratic_attn, 1.0);
+            assert_eq!(light.tex_info, -1);
+        }
+    }
+
+    #[test]
+    fn parse_overlay() {
+        let mut data = 7i32.to_le_bytes().to_vec();
+        data.extend(3i16.to_le_bytes());
+        data.extend((1u16 << 14 | 2).to_le_bytes());
+        let mut faces = [0i32; 64];
+        faces[..2].copy_from_slice(&[10, 11]);
+        data.extend(faces.iter().flat_map(|f| f.to_le_bytes()));
+        data.extend(bytes(&[0.0, 1.0, 0.25, 0.75]));
+        data.extend(bytes(&[
+            -8.0, -8.0, 1.0, -8.0, 8.0, 0.0, 8.0, 8.0, 0.0, 8.0, -8.0, 0.0,
+        ]));
+        data.extend(bytes(&[0.0, 0.0, 64.0, 0.0, 0.0, 1.0]));
+        assert_eq!(data.len(), 352);
+
+        let overlay: BspOverlay = Cursor::new(&data).read_le().unwrap();
+        assert_eq!(overlay.id, 7);
+        assert_eq!(overlay.faces(), [10, 11]);
+        assert_eq!(overlay.render_order(), 1);
+        assert_eq!(overlay.basis(), [Vec3::X, Vec3::Y, Vec3::Z]);
+        assert_eq!(overlay.corners()[1], Vec3::new(-8.0, 8.0, 64.0));
+        assert_eq!(overlay.tex_coords()[2], Vec2::new(1.0, 0.75));
+
+        // Water overlays only differ in their face count
+        let mut water = data[..8].to_vec();
+        water.extend([0; 1024]);
+        water.extend(&data[8 + 256..]);
+        assert_eq!(water.len(), 1120);
+        let overlay: BspWaterOverlay = Cursor::new(water).read_le().unwrap();
+        assert_eq!(overlay.face_count(), 2);
+        assert_eq!(overlay.origin, [0.0, 0.0, 64.0]);
+    }
+}
@@ -0,0 +1,449 @@
+use binrw::{BinRead, BinReaderExt, BinWrite, BinWriterExt};
+use eyre::{bail, ensure, OptionExt};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    decompress_lzma,
+    writer::{compress_lzma, BspLumps, LUMP_PAKFILE},
+    BspFile, LzmaHeader,
+};
+
+const METHOD_STORE: u16 = 0;
+const METHOD_LZMA: u16 = 14;
+
+/// LZMA SDK version written in front of LZMA entries
+const LZMA_SDK_VERSION: [u8; 2] = [9, 20];
+
+/// 1980-01-01 00:00, the earliest date zip can store
+const DEFAULT_DOS_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PakCompression {
+    #[default]
+    Store,
+    Lzma,
+}
+
+#[derive(BinRead, BinWrite, Debug)]
+#[brw(magic = b"PK\x03\x04")]
+struct LocalFileHeader {
+    version_needed: u16,
+    flags: u16,
+    method: u16,
+    modified_time: u16,
+    modified_date: u16,
+    crc32: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    name_length: u16,
+    extra_length: u16,
+}
+
+#[derive(BinRead, BinWrite, Debug)]
+#[brw(magic = b"PK\x01\x02")]
+struct CentralDirectoryEntry {
+    version_made_by: u16,
+    version_needed: u16,
+    flags: u16,
+    method: u16,
+    modified_time: u16,
+    modified_date: u16,
+    crc32: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    name_length: u16,
+    extra_length: u16,
+    comment_length: u16,
+    disk_start: u16,
+    internal_attributes: u16,
+    external_attributes: u32,
+    local_header_offset: u32,
+    #[br(count = name_length)]
+    name: Vec<u8>,
+    #[br(count = extra_length)]
+    extra: Vec<u8>,
+    #[br(count = comment_length)]
+    comment: Vec<u8>,
+}
+
+#[derive(BinRead, BinWrite, Debug)]
+#[brw(magic = b"PK\x05\x06")]
+struct EndOfCentralDirectory {
+    disk_number: u16,
+    directory_disk: u16,
+    disk_entries: u16,
+    total_entries: u16,
+    directory_size: u32,
+    directory_offset: u32,
+    comment_length: u16,
+    #[br(count = comment_length)]
+    comment: Vec<u8>,
+}
+
+/// A file inside the pakfile
+#[derive(Debug, Clone)]
+pub struct PakEntry {
+    pub name: String,
+    pub compression: PakCompression,
+    pub crc32: u32,
+    /// Uncompressed size
+    pub size: u32,
+    /// DOS time and date
+    modified: (u16, u16),
+    /// Data as it is stored in the archive
+    data: Vec<u8>,
+}
+
+impl PakEntry {
+    pub fn new(name: &str, data: &[u8], compression: PakCompression) -> eyre::Result<Self> {
+        let mut entry = Self {
+            name: name.replace('\\', "/"),
+            compression: PakCompression::Store,
+            crc32: crc32fast::hash(data),
+            size: data.len() as u32,
+            modified: (0, DEFAULT_DOS_DATE),
+            data: data.to_vec(),
+        };
+        entry.set_compression(compression)?;
+        Ok(entry)
+    }
+
+    /// Size of the data as stored in the archive
+    pub fn compressed_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Decompresses the file and verifies its checksum
+    pub fn read(&self) -> eyre::Result<Vec<u8>> {
+        let data = match self.compression {
+            PakCompression::Store => self.data.clone(),
+            PakCompression::Lzma => {
+                let mut c = Cursor::new(&self.data);
+                let [_major, _minor]: [u8; 2] = c.read_le()?;
+                let properties_size: u16 = c.read_le()?;
+                ensure!(
+                    properties_size == 5,
+                    "Unsupported LZMA properties size {properties_size}"
+                );
+                let properties: [u8; 5] = c.read_le()?;
+                let compressed = &self.data[c.position() as usize..];
+                let header = LzmaHeader {
+                    actual_size: self.size,
+                    lzma_size: compressed.len() as u32,
+                    properties,
+                };
+                decompress_lzma(&header, compressed)?
+            }
+        };
+
+        ensure!(
+            crc32fast::hash(&data) == self.crc32,
+            "Checksum mismatch for '{}'",
+            self.name
+        );
+        Ok(data)
+    }
+
+    /// Recompresses the entry. LZMA is only used if it makes the file smaller
+    pub fn set_compression(&mut self, compression: PakCompression) -> eyre::Result<()> {
+        if compression == self.compression {
+            return Ok(());
+        }
+
+        let data = self.read()?;
+        match compression {
+            PakCompression::Store => self.data = data,
+            PakCompression::Lzma => {
+                let (properties, compressed) = compress_lzma(&data)?;
+                let compressed_size = 4 + properties.len() + compressed.len();
+                if compressed_size >= data.len() {
+                    return Ok(());
+                }
+
+                let mut out = Vec::with_capacity(compressed_size);
+                out.extend_from_slice(&LZMA_SDK_VERSION);
+                out.extend_from_slice(&(properties.len() as u16).to_le_bytes());
+                out.extend_from_slice(&properties);
+                out.extend_from_slice(&compressed);
+                self.data = out;
+            }
+        }
+
+        self.compression = compression;
+        Ok(())
+    }
+
+    fn method(&self) -> u16 {
+        match self.compression {
+            PakCompression::Store => METHOD_STORE,
+            PakCompression::Lzma => METHOD_LZMA,
+        }
+    }
+
+    fn version_needed(&self) -> u16 {
+        match self.compression {
+            PakCompression::Store => 10,
+            PakCompression::Lzma => 63,
+        }
+    }
+}
+
+/// The zip archive embedded in the pakfile lump (40)
+#[derive(Debug, Clone, Default)]
+pub struct PakFile {
+    pub entries: Vec<PakEntry>,
+    pub comment: Vec<u8>,
+}
+
+impl PakFile {
+    pub fn parse(data: &[u8]) -> eyre::Result<Self> {
+        if data.is_empty() {
+            return Ok(Self::default());
+        }
+
+        // The end of central directory record is followed by a comment of up to 64KiB
+        ensure!(data.len() >= 22, "Pakfile is too small to be a zip archive");
+        let search_start = data.len().saturating_sub(22 + u16::MAX as usize);
+        let eocd_offset = (search_start..=data.len() - 22)
+            .rev()
+            .find(|&i| data[i..].starts_with(b"PK\x05\x06"))
+            .ok_or_eyre("Pakfile has no end of central directory record")?;
+
+        let mut c = Cursor::new(data);
+        c.seek(SeekFrom::Start(eocd_offset as u64))?;
+        let eocd: EndOfCentralDirectory = c.read_le()?;
+
+        c.seek(SeekFrom::Start(eocd.directory_offset as u64))?;
+        let mut entries = Vec::with_capacity(eocd.total_entries as usize);
+        for _ in 0..eocd.total_entries {
+            let cd: CentralDirectoryEntry = c.read_le()?;
+            let name = String::from_utf8_lossy(&cd.name).to_string();
+            let compression = match cd.method {
+                METHOD_STORE => PakCompression::Store,
+                METHOD_LZMA => PakCompression::Lzma,
+                m => bail!("'{name}' uses unsupported compression method {m}"),
+            };
+
+            // Sizes in the local header may be zero if they're stored in a data descriptor, so only use it to find the data
+            let directory_position = c.position();
+            c.seek(SeekFrom::Start(cd.local_header_offset as u64))?;
+            let local: LocalFileHeader = c.read_le()?;
+            let start =
+                c.position() as usize + local.name_length as usize + local.extra_length as usize;
+            let file_data = data
+                .get(start..start + cd.compressed_size as usize)
+                .ok_or_else(|| {
+                    eyre::eyre!("Data of '{name}' extends past the end of the pakfile")
+                })?;
+            c.seek(SeekFrom::Start(directory_position))?;
+
+            entries.push(PakEntry {
+                name,
+                compression,
+                crc32: cd.crc32,
+                size: cd.uncompressed_size,
+                modified: (cd.modified_time, cd.modified_date),
+                data: file_data.to_vec(),
+            });
+        }
+
+        Ok(Self {
+            entries,
+            comment: eocd.comment,
+        })
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.name.as_str())
+    }
+
+    /// Finds a file by path. Paths are case insensitive and can use either slash
+    pub fn entry(&self, path: &str) -> Option<&PakEntry> {
+        let path = normalize_path(path);
+        self.entries
+            .iter()
+            .find(|e| normalize_path(&e.name) == path)
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.entry(path).is_some()
+    }
+
+    pub fn read(&self, path: &str) -> eyre::Result<Option<Vec<u8>>> {
+        self.entry(path).map(|e| e.read()).transpose()
+    }
+
+    /// Adds a file, replacing any existing file with the same path
+    pub fn add(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        compression: PakCompression,
+    ) -> eyre::Result<()> {
+        let entry = PakEntry::new(path, data, compression)?;
+        let normalized = normalize_path(path);
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| normalize_path(&e.name) == normalized)
+        {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+
+        Ok(())
+    }
+
+    /// Removes a file, returns whether it existed
+    pub fn remove(&mut self, path: &str) -> bool {
+        let path = normalize_path(path);
+        let count = self.entries.len();
+        self.entries.retain(|e| normalize_path(&e.name) != path);
+        self.entries.len() != count
+    }
+
+    /// Recompresses every file
+    pub fn repack(&mut self, compression: PakCompression) -> eyre::Result<()> {
+        for entry in &mut self.entries {
+            entry.set_compression(compression)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write<W: Write + Seek>(&self, mut writer: W) -> eyre::Result<()> {
+        ensure!(
+            self.entries.len() <= u16::MAX as usize,
+            "Too many files in pakfile"
+        );
+
+        // Offsets are relative to the start of the archive, not the BSP
+        let start = writer.stream_position()?;
+        let mut directory = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let local_header_offset = (writer.stream_position()? - start) as u32;
+            let name = entry.name.as_bytes();
+            writer.write_le(&LocalFileHeader {
+                version_needed: entry.version_needed(),
+                flags: 0,
+                method: entry.method(),
+                modified_time: entry.modified.0,
+                modified_date: entry.modified.1,
+                crc32: entry.crc32,
+                compressed_size: entry.data.len() as u32,
+                uncompressed_size: entry.size,
+                name_length: name.len() as u16,
+                extra_length: 0,
+            })?;
+            writer.write_all(name)?;
+            writer.write_all(&entry.data)?;
+
+            directory.push(CentralDirectoryEntry {
+                version_made_by: entry.version_needed(),
+                version_needed: entry.version_needed(),
+                flags: 0,
+                method: entry.method(),
+                modified_time: entry.modified.0,
+                modified_date: entry.modified.1,
+                crc32: entry.crc32,
+                compressed_size: entry.data.len() as u32,
+                uncompressed_size: entry.size,
+                name_length: name.len() as u16,
+                extra_length: 0,
+                comment_length: 0,
+                disk_start: 0,
+                internal_attributes: 0,
+                external_attributes: 0,
+                local_header_offset,
+                name: name.to_vec(),
+                extra: vec![],
+                comment: vec![],
+            });
+        }
+
+        let directory_offset = writer.stream_position()? - start;
+        for entry in &directory {
+            writer.write_le(entry)?;
+        }
+        let directory_size = writer.stream_position()? - start - directory_offset;
+
+        writer.write_le(&EndOfCentralDirectory {
+            disk_number: 0,
+            directory_disk: 0,
+            disk_entries: directory.len() as u16,
+            total_entries: directory.len() as u16,
+            directory_size: directory_size as u32,
+            directory_offset: directory_offset as u32,
+            comment_length: self.comment.len() as u16,
+            comment: self.comment.clone(),
+        })?;
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        let mut c = Cursor::new(vec![]);
+        self.write(&mut c)?;
+        Ok(c.into_inner())
+    }
+}
+
+fn normalize_path(path: &str) -> String {
+    let mut path = path.to_lowercase().replace('\\', "/");
+    // Eliminate double path separators
+    while path.contains("//") {
+        path = path.replace("//", "/");
+    }
+
+    path.trim_start_matches('/').to_string()
+}
+
+impl<R: Read + Seek> BspFile<R> {
+    pub fn read_pakfile(&mut self) -> eyre::Result<PakFile> {
+        PakFile::parse(&self.read_lump_raw(LUMP_PAKFILE)?)
+    }
+}
+
+impl BspLumps {
+    pub fn pakfile(&self) -> eyre::Result<PakFile> {
+        PakFile::parse(&self.lumps[LUMP_PAKFILE].data)
+    }
+
+    /// Replaces the pakfile lump. Use [`crate::lumpfile::patch_lump`] to update a map in place instead
+    pub fn set_pakfile(&mut self, pakfile: &PakFile) -> eyre::Result<()> {
+        self.set_lump(LUMP_PAKFILE, pakfile.to_bytes()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let text = b"\"VertexLitGeneric\" {}\n".repeat(50);
+        let mut pak = PakFile::default();
+        pak.add("materials/test.vmt", &text, PakCompression::Lzma)
+            .unwrap();
+        pak.add("sound\\test.wav", b"RIFF", PakCompression::Lzma)
+            .unwrap();
+        assert_eq!(pak.entries[0].compression, PakCompression::Lzma);
+        // Not worth compressing
+        assert_eq!(pak.entries[1].compression, PakCompression::Store);
+
+        let mut pak = PakFile::parse(&pak.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            pak.files().collect::<Vec<_>>(),
+            ["materials/test.vmt", "sound/test.wav"]
+        );
+        assert_eq!(pak.read("Materials\\TEST.vmt").unwrap().unwrap(), text);
+
+        pak.repack(PakCompression::Store).unwrap();
+        assert!(pak.remove("sound/test.wav"));
+        let pak = PakFile::parse(&pak.to_bytes().unwrap()).unwrap();
+        assert_eq!(pak.entries.len(), 1);
+        assert_eq!(pak.entries[0].compressed_size(), text.len());
+        assert_eq!(pak.read("materials/test.vmt").unwrap().unwrap(), text);
+    }
+}
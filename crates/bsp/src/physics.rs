@@ -0,0 +1,325 @@
+use binrw::{BinRead, BinReaderExt};
+use eyre::{ensure, Context};
+use glam::Vec3;
+use std::io::{Cursor, Seek, SeekFrom};
+
+use crate::Bsp;
+
+/// IVP uses meters, Source uses inches
+const METERS_TO_INCHES: f32 = 39.37008;
+
+const LEDGETREE_NODE_SIZE: u64 = 28;
+const LEDGE_SIZE: u64 = 16;
+
+/// Collision model of a single brush model
+#[derive(Debug, Clone)]
+pub struct PhysModel {
+    /// Index into the models lump
+    pub model_index: i32,
+    pub solids: Vec<PhysSolid>,
+    /// Key/value text describing the solids, same as in .phy files
+    pub key_data: String,
+}
+
+/// A single physics object, made up of convex pieces
+#[derive(Debug, Clone, Default)]
+pub struct PhysSolid {
+    pub convexes: Vec<PhysConvex>,
+}
+
+/// Triangulated convex hull, in Source coordinates
+#[derive(Debug, Clone, Default)]
+pub struct PhysConvex {
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(BinRead, Debug)]
+struct PhysModelHeader {
+    model_index: i32,
+    _data_size: i32,
+    key_data_size: i32,
+    solid_count: i32,
+}
+
+#[derive(BinRead, Debug)]
+#[br(magic = b"VPHY")]
+struct CompactSurfaceHeader {
+    _version: i16,
+    model_type: i16,
+    _surface_size: i32,
+    _drag_axis_areas: [f32; 3],
+    _axis_map_size: i32,
+}
+
+#[derive(BinRead, Debug)]
+struct CompactSurface {
+    _mass_center: [f32; 3],
+    _rotation_inertia: [f32; 3],
+    _upper_limit_radius: f32,
+    _size_and_deviation: u32,
+    offset_ledgetree_root: i32,
+    _reserved: [i32; 2],
+    #[br(assert(&_magic == b"IVPS", "Invalid compact surface magic {:?}", _magic))]
+    _magic: [u8; 4],
+}
+
+#[derive(BinRead, Debug)]
+struct LedgetreeNode {
+    offset_right_node: i32,
+    offset_compact_ledge: i32,
+    _center: [f32; 3],
+    _radius: f32,
+    _box_sizes: [u8; 4],
+}
+
+#[derive(BinRead, Debug)]
+struct CompactLedge {
+    point_offset: i32,
+    _client_data: i32,
+    _flags_and_size: u32,
+    triangle_count: i16,
+    _reserved: i16,
+}
+
+#[derive(BinRead, Debug)]
+struct CompactTriangle {
+    _indices_and_material: u32,
+    /// Start point index in the low 16 bits
+    edges: [u32; 3],
+}
+
+/// Parses the PHYSCOLLIDE lump (29)
+pub fn parse_phys_collide(data: &[u8]) -> eyre::Result<Vec<PhysModel>> {
+    let mut c = Cursor::new(data);
+    let mut models = vec![];
+    while (c.position() as usize) < data.len() {
+        let header: PhysModelHeader = c.read_le()?;
+        if header.model_index == -1 {
+            break;
+        }
+
+        let mut solids = Vec::with_capacity(header.solid_count.max(0) as usize);
+        for _ in 0..header.solid_count {
+            let size: i32 = c.read_le()?;
+            let start = c.position() as usize;
+            let end = start + size.max(0) as usize;
+            ensure!(end <= data.len(), "Collision solid extends past the lump");
+
+            solids.push(
+                parse_solid(&data[start..end])
+                    .with_context(|| format!("Model {}", header.model_index))?,
+            );
+            c.seek(SeekFrom::Start(end as u64))?;
+        }
+
+        let start = c.position() as usize;
+        let end = (start + header.key_data_size.max(0) as usize).min(data.len());
+        let key_data = String::from_utf8_lossy(&data[start..end])
+            .trim_end_matches('\0')
+            .to_string();
+        c.seek(SeekFrom::Start(end as u64))?;
+
+        models.push(PhysModel {
+            model_index: header.model_index,
+            solids,
+            key_data,
+        });
+    }
+
+    Ok(models)
+}
+
+/// Parses a single collision solid, with or without a `VPHY` header
+pub fn parse_solid(data: &[u8]) -> eyre::Result<PhysSolid> {
+    let mut c = Cursor::new(data);
+    let surface_start = match c.read_le::<CompactSurfaceHeader>() {
+        Ok(header) => {
+            // Other model types (eg. MOPP) aren't compact surfaces
+            if header.model_type != 0 {
+                return Ok(PhysSolid::default());
+            }
+            c.position()
+        }
+        Err(_) => 0,
+    };
+
+    c.seek(SeekFrom::Start(surface_start))?;
+    let surface: CompactSurface = c.read_le()?;
+
+    let mut solid = PhysSolid::default();
+    let root = surface_start as i64 + surface.offset_ledgetree_root as i64;
+    walk_ledgetree(&mut c, root, &mut solid)?;
+    Ok(solid)
+}
+
+/// Collects the convexes of every terminal node
+///
+/// Children always come after their parent, and a valid tree can't have more nodes than fit in the
+/// data, which stops crafted trees from looping or revisiting shared nodes
+fn walk_ledgetree(c: &mut Cursor<&[u8]>, root: i64, solid: &mut PhysSolid) -> eyre::Result<()> {
+    let mut budget = c.get_ref().len() as u64 / LEDGETREE_NODE_SIZE;
+    let mut stack = vec![root];
+    while let Some(node_offset) = stack.pop() {
+        ensure!(
+            budget > 0,
+            "Ledge tree has more nodes than fit in the solid"
+        );
+        budget -= 1;
+        ensure!(node_offset >= 0, "Invalid ledge tree node offset");
+
+        c.seek(SeekFrom::Start(node_offset as u64))?;
+        let node: LedgetreeNode = c.read_le()?;
+        if node.offset_right_node == 0 {
+            let ledge = node_offset + node.offset_compact_ledge as i64;
+            solid.convexes.push(read_ledge(c, ledge)?);
+            continue;
+        }
+
+        ensure!(
+            node.offset_right_node > 0,
+            "Ledge tree node points at an earlier node"
+        );
+        // The left child directly follows its parent
+        stack.push(node_offset + node.offset_right_node as i64);
+        stack.push(node_offset + LEDGETREE_NODE_SIZE as i64);
+    }
+
+    Ok(())
+}
+
+fn read_ledge(c: &mut Cursor<&[u8]>, ledge_offset: i64) -> eyre::Result<PhysConvex> {
+    ensure!(ledge_offset >= 0, "Invalid ledge offset");
+    c.seek(SeekFrom::Start(ledge_offset as u64))?;
+    let ledge: CompactLedge = c.read_le()?;
+
+    let mut triangles = Vec::with_capacity(ledge.triangle_count.max(0) as usize);
+    c.seek(SeekFrom::Start(ledge_offset as u64 + LEDGE_SIZE))?;
+    for _ in 0..ledge.triangle_count {
+        triangles.push(c.read_le::<CompactTriangle>()?);
+    }
+
+    // Point indices are shared by all ledges of a solid, remap them to the hull
+    let points_offset = ledge_offset + ledge.point_offset as i64;
+    ensure!(points_offset >= 0, "Invalid ledge point offset");
+    let mut convex = PhysConvex::default();
+    let mut remap = std::collections::HashMap::new();
+    for tri in &triangles {
+        for edge in tri.edges {
+            let point = edge & 0xFFFF;
+            let index = match remap.get(&point) {
+                Some(&i) => i,
+                None => {
+                    c.seek(SeekFrom::Start(points_offset as u64 + point as u64 * 16))?;
+                    let [x, y, z, _hesse]: [f32; 4] = c.read_le()?;
+                    let i = convex.vertices.len() as u32;
+                    convex.vertices.push(Vec3::new(x, z, -y) * METERS_TO_INCHES);
+                    remap.insert(point, i);
+                    i
+                }
+            };
+
+            convex.indices.push(index);
+        }
+    }
+
+    Ok(convex)
+}
+
+impl Bsp {
+    /// Returns the collision model for a brush model, if it has one
+    pub fn phys_model(&self, model_index: usize) -> Option<&PhysModel> {
+        self.phys_models
+            .iter()
+            .find(|m| m.model_index == model_index as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(out: &mut Vec<u8>, values: &[i32]) {
+        out.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    }
+
+    fn push_f32(out: &mut Vec<u8>, values: &[f32]) {
+        out.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    }
+
+    /// A lump with a single tetrahedron
+    fn test_lump() -> Vec<u8> {
+        let mut solid = b"VPHY".to_vec();
+        solid.extend_from_slice(&[0, 1, 0, 0]);
+        push(&mut solid, &[0, 0, 0, 0, 0]);
+
+        // Compact surface, the ledge tree node is placed after the ledge and its points
+        push_f32(&mut solid, &[0.0; 7]);
+        push(&mut solid, &[0, 48 + 16 + 64 + 64, 0, 0]);
+        solid.extend_from_slice(b"IVPS");
+
+        // Ledge with 4 triangles, points right after them
+        push(&mut solid, &[16 + 64, 0, 0]);
+        solid.extend_from_slice(&[4, 0, 0, 0]);
+        for tri in [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]] {
+            push(&mut solid, &[0, tri[0], tri[1], tri[2]]);
+        }
+        for p in [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ] {
+            push_f32(&mut solid, &[p[0], p[1], p[2], 0.0]);
+        }
+
+        // Terminal node pointing back at the ledge
+        push(&mut solid, &[0, -(16 + 64 + 64)]);
+        push_f32(&mut solid, &[0.0; 4]);
+        push(&mut solid, &[0]);
+
+        let key_data = b"solid {}\0";
+        let mut lump = vec![];
+        push(
+            &mut lump,
+            &[0, 4 + solid.len() as i32, key_data.len() as i32, 1],
+        );
+        push(&mut lump, &[solid.len() as i32]);
+        lump.extend_from_slice(&solid);
+        lump.extend_from_slice(key_data);
+        push(&mut lump, &[-1, -1, 0, 0]);
+        lump
+    }
+
+    #[test]
+    fn parse_tetrahedron() {
+        let models = parse_phys_collide(&test_lump()).unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].key_data, "solid {}");
+
+        let convex = &models[0].solids[0].convexes[0];
+        assert_eq!(convex.vertices.len(), 4);
+        assert_eq!(convex.indices.len(), 12);
+        // IVP Y points down, Z forward
+        assert_eq!(convex.vertices[2], Vec3::new(0.0, 0.0, -METERS_TO_INCHES));
+        assert_eq!(convex.vertices[3], Vec3::new(0.0, METERS_TO_INCHES, 0.0));
+    }
+
+    #[test]
+    fn reject_cyclic_ledgetree() {
+        // A chain of nodes whose children are both the next node, and a node pointing at itself
+        for right_offset in [LEDGETREE_NODE_SIZE as i32, -(LEDGETREE_NODE_SIZE as i32)] {
+            let mut solid = vec![];
+            push_f32(&mut solid, &[0.0; 7]);
+            push(&mut solid, &[0, 48, 0, 0]);
+            solid.extend_from_slice(b"IVPS");
+            for _ in 0..300 {
+                push(&mut solid, &[right_offset, 0]);
+                push_f32(&mut solid, &[0.0; 4]);
+                push(&mut solid, &[0]);
+            }
+
+            assert!(parse_solid(&solid).is_err());
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use binrw::Endian;
+
+use crate::{gamelumps::StaticPropLump, BspHeader, BSP_LUMP_COUNT};
+
+/// Size of the lump directory header, lump data can't start before this
+const HEADER_SIZE: u32 = 4 + 4 + BSP_LUMP_COUNT as u32 * 16 + 4;
+
+/// Field order of the entries in the lump directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BspLumpLayout {
+    /// offset, length, version, fourcc
+    #[default]
+    Standard,
+    /// version, offset, length, fourcc. Used by Left 4 Dead 2 and its branches
+    L4d2,
+}
+
+impl BspLumpLayout {
+    /// Guesses the layout from raw lump directory entries, which can't be told apart by version alone
+    pub(crate) fn detect(version: i32, entries: &[[u32; 3]]) -> Self {
+        // In the right layout every non-empty lump starts after the header
+        let plausible = |offset: fn(&[u32; 3]) -> (u32, u32)| {
+            entries.iter().all(|e| {
+                let (offset, length) = offset(e);
+                length == 0 || offset >= HEADER_SIZE
+            })
+        };
+
+        if version >= 21 && !plausible(|e| (e[0], e[1])) && plausible(|e| (e[1], e[2])) {
+            BspLumpLayout::L4d2
+        } else {
+            BspLumpLayout::Standard
+        }
+    }
+}
+
+/// Games that store different struct layouts under the same version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BspGame {
+    /// Layouts are picked by version, with heuristics where games disagree
+    #[default]
+    Generic,
+    Tf2,
+    L4d2,
+    Csgo,
+}
+
+/// Describes which variant of the format a map uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BspProfile {
+    pub game: BspGame,
+    pub endian: Endian,
+    pub lump_layout: BspLumpLayout,
+}
+
+impl Default for BspProfile {
+    fn default() -> Self {
+        Self {
+            game: BspGame::Generic,
+            endian: Endian::Little,
+            lump_layout: BspLumpLayout::Standard,
+        }
+    }
+}
+
+impl BspProfile {
+    /// Builds a profile from everything that can be detected from the header
+    pub fn detect(header: &BspHeader) -> Self {
+        Self {
+            game: match header.lump_layout {
+                BspLumpLayout::L4d2 => BspGame::L4d2,
+                BspLumpLayout::Standard => BspGame::Generic,
+            },
+            endian: header.endian,
+            lump_layout: header.lump_layout,
+        }
+    }
+
+    pub fn with_game(self, game: BspGame) -> Self {
+        Self { game, ..self }
+    }
+
+    /// Whether version 10 static props use TF2's layout, which is smaller than the one other games use
+    pub fn static_prop_tf2_layout(&self, version: u16, stride: usize) -> bool {
+        version == 10
+            && match self.game {
+                BspGame::Tf2 => true,
+                BspGame::Generic => stride < StaticPropLump::size(10, false),
+                BspGame::L4d2 | BspGame::Csgo => false,
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        writer::{BspCompression, BspLumpData, BspLumps},
+        BspFile, BspLump,
+    };
+    use binrw::{BinReaderExt, BinWriterExt};
+    use std::io::Cursor;
+
+    #[test]
+    fn detect_l4d2_layout() {
+        let mut lumps = BspLumps::new(21, 1);
+        lumps.lump_layout = BspLumpLayout::L4d2;
+        lumps.lumps[3] = BspLumpData {
+            version: 1,
+            data: vec![3; 24],
+        };
+
+        let mut out = Cursor::new(vec![]);
+        lumps.write(&mut out, BspCompression::None).unwrap();
+        let mut file = BspFile::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(file.header.lump_layout, BspLumpLayout::L4d2);
+        assert_eq!(file.header.lumps[3].version, 1);
+        assert_eq!(file.read_lump_raw(3).unwrap(), vec![3; 24]);
+        assert_eq!(BspProfile::detect(&file.header).game, BspGame::L4d2);
+    }
+
+    #[test]
+    fn big_endian_header() {
+        let mut header = BspHeader {
+            version: 20,
+            lumps: vec![
+                BspLump {
+                    offset: 0,
+                    length: 0,
+                    version: 0,
+                    fourcc: [0; 4],
+                };
+                BSP_LUMP_COUNT
+            ],
+            map_revision: 7,
+            endian: Endian::Big,
+            lump_layout: BspLumpLayout::Standard,
+        };
+        header.lumps[1].offset = HEADER_SIZE;
+        header.lumps[1].length = 20;
+
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&header).unwrap();
+        let data = out.into_inner();
+        assert!(data.starts_with(b"PSBV\0\0\0\x14"));
+
+        let read: BspHeader = Cursor::new(data).read_le().unwrap();
+        assert_eq!(read.endian, Endian::Big);
+        assert_eq!(read.map_revision, 7);
+        assert_eq!(read.lumps[1].length, 20);
+    }
+}
@@ -0,0 +1,163 @@
+use binrw::BinReaderExt;
+use eyre::{ensure, OptionExt};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap},
+    io::Cursor,
+};
+
+use crate::{decompress_lzma, BspHeader, BspLumpReader, LzmaHeader, BSP_LUMP_COUNT};
+
+/// BSP file backed by a byte slice, such as a memory mapped file
+///
+/// Uncompressed lumps are borrowed straight from the slice. LZMA compressed lumps are decompressed once and cached
+pub struct BspSlice<'a> {
+    data: &'a [u8],
+    pub header: BspHeader,
+    lump_cache: Vec<Option<Vec<u8>>>,
+    /// Decompressed game lumps, keyed by file offset
+    offset_cache: HashMap<u64, Vec<u8>>,
+}
+
+impl<'a> BspSlice<'a> {
+    pub fn new(data: &'a [u8]) -> eyre::Result<Self> {
+        let header = Cursor::new(data).read_le::<BspHeader>()?;
+        Ok(Self {
+            data,
+            header,
+            lump_cache: vec![None; BSP_LUMP_COUNT],
+            offset_cache: HashMap::new(),
+        })
+    }
+
+    /// Returns the lump exactly as it is stored in the file, without decompressing it
+    pub fn raw_lump(&self, index: usize) -> eyre::Result<&'a [u8]> {
+        let lump = self
+            .header
+            .lumps
+            .get(index)
+            .ok_or_eyre("Lump index out of bounds")?;
+
+        self.raw_range(lump.offset as u64, lump.length as usize)
+    }
+
+    pub fn is_lump_compressed(&self, index: usize) -> bool {
+        self.raw_lump(index)
+            .is_ok_and(|data| data.starts_with(b"LZMA"))
+    }
+
+    /// Drops all cached decompressed lumps
+    pub fn clear_cache(&mut self) {
+        self.lump_cache.iter_mut().for_each(|c| *c = None);
+        self.offset_cache.clear();
+    }
+
+    fn raw_range(&self, offset: u64, length: usize) -> eyre::Result<&'a [u8]> {
+        let data = self.data;
+        let start = offset as usize;
+        ensure!(
+            start
+                .checked_add(length)
+                .is_some_and(|end| end <= data.len()),
+            "Lump at {offset:#x} ({length} bytes) extends past the end of the file"
+        );
+
+        Ok(&data[start..start + length])
+    }
+}
+
+/// Decompresses `data` if it's an LZMA lump, returns `None` for uncompressed data
+fn decompress_slice(data: &[u8]) -> eyre::Result<Option<Vec<u8>>> {
+    if !data.starts_with(b"LZMA") {
+        return Ok(None);
+    }
+
+    let mut c = Cursor::new(&data[4..]);
+    let header: LzmaHeader = c.read_le()?;
+    let start = 4 + c.position() as usize;
+    let compressed = data
+        .get(start..start + header.lzma_size as usize)
+        .ok_or_eyre("LZMA lump is truncated")?;
+
+    decompress_lzma(&header, compressed).map(Some)
+}
+
+impl BspLumpReader for BspSlice<'_> {
+    fn header(&self) -> &BspHeader {
+        &self.header
+    }
+
+    fn lump_bytes(&mut self, index: usize) -> eyre::Result<Cow<'_, [u8]>> {
+        let raw = self.raw_lump(index)?;
+        if self.lump_cache[index].is_none() {
+            match decompress_slice(raw)? {
+                Some(data) => self.lump_cache[index] = Some(data),
+                None => return Ok(Cow::Borrowed(raw)),
+            }
+        }
+
+        Ok(Cow::Borrowed(self.lump_cache[index].as_deref().unwrap()))
+    }
+
+    fn lump_bytes_at(&mut self, offset: u64, length: usize) -> eyre::Result<Cow<'_, [u8]>> {
+        let raw = self.raw_range(offset, length)?;
+        if let Entry::Vacant(entry) = self.offset_cache.entry(offset) {
+            match decompress_slice(raw)? {
+                Some(data) => {
+                    entry.insert(data);
+                }
+                None => return Ok(Cow::Borrowed(raw)),
+            }
+        }
+
+        Ok(Cow::Borrowed(&self.offset_cache[&offset]))
+    }
+}
+
+/// Memory maps a file for use with [`BspSlice`]
+#[cfg(feature = "mmap")]
+pub fn map_file(path: impl AsRef<std::path::Path>) -> eyre::Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: The file may be modified by other processes while it's mapped, which is the caller's responsibility
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        writer::{BspCompression, BspLumps},
+        BspFile,
+    };
+
+    fn test_file(compression: BspCompression) -> Vec<u8> {
+        let mut lumps = BspLumps::new(20, 1);
+        lumps.set_lump(0, b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec());
+        lumps.set_lump(3, [0u8; 12].repeat(64));
+
+        let mut out = Cursor::new(vec![]);
+        lumps.write(&mut out, compression).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn borrows_uncompressed_lumps() {
+        let data = test_file(BspCompression::None);
+        let mut slice = BspSlice::new(&data).unwrap();
+        assert!(matches!(slice.lump_bytes(3).unwrap(), Cow::Borrowed(_)));
+        assert!(slice.entities().unwrap().contains("worldspawn"));
+    }
+
+    #[test]
+    fn matches_streamed_file() {
+        let data = test_file(BspCompression::Lzma);
+        let mut slice = BspSlice::new(&data).unwrap();
+        let mut file = BspFile::new(Cursor::new(&data)).unwrap();
+        assert!(slice.is_lump_compressed(3));
+        assert_eq!(
+            *slice.lump_bytes(3).unwrap(),
+            file.read_lump_raw(3).unwrap()
+        );
+        assert_eq!(slice.entities().unwrap(), file.entities().unwrap());
+    }
+}
@@ -0,0 +1,507 @@
+use glam::Vec3;
+
+use crate::{
+    flags::BspContents,
+    lumps::{BspBrush, BspNode, BspPlane},
+    Bsp,
+};
+
+/// Distance traces are kept away from brush surfaces, matching the engine
+const DIST_EPSILON: f32 = 0.03125;
+
+#[derive(Debug, Clone)]
+pub struct BspTrace {
+    /// How far along the ray the trace got before hitting something, 1.0 if nothing was hit
+    pub fraction: f32,
+    pub end_position: Vec3,
+    /// The trace started inside a solid brush
+    pub start_solid: bool,
+    /// The trace never left a solid brush
+    pub all_solid: bool,
+    /// The plane that was hit
+    pub plane: Option<BspPlane>,
+    /// Texinfo of the brush side that was hit, -1 if there is none
+    pub tex_info: i16,
+    /// Contents of the brush that was hit
+    pub contents: BspContents,
+    /// Index of the brush that was hit
+    pub brush: Option<usize>,
+}
+
+struct TraceWork {
+    start: Vec3,
+    end: Vec3,
+    /// Half-size of the box being swept, zero for rays
+    extents: Vec3,
+    is_point: bool,
+    mask: BspContents,
+    checked_brushes: Vec<bool>,
+    trace: BspTrace,
+}
+
+/// Nodes are stored depth first, so children always come after their parent
+///
+/// Children pointing back up the tree would loop forever, they are replaced by the solid leaf
+fn node_children(node: i32, n: &BspNode) -> [i32; 2] {
+    n.children.map(|c| if c < 0 || c > node { c } else { -1 })
+}
+
+impl Bsp {
+    /// Returns the index of the leaf containing `point`
+    ///
+    /// Leaf 0 is the solid leaf outside of the world, which is also returned for malformed trees
+    pub fn find_leaf(&self, point: Vec3) -> usize {
+        let head_node = self.models.first().map(|m| m.head_node).unwrap_or(0);
+        self.find_leaf_from(head_node, point)
+    }
+
+    fn find_leaf_from(&self, mut node: i32, point: Vec3) -> usize {
+        while node >= 0 {
+            let Some(n) = self.nodes.get(node as usize) else {
+                return 0;
+            };
+            let Some(plane) = self.planes.get(n.plane_num as usize) else {
+                return 0;
+            };
+
+            let d = Vec3::from(plane.normal).dot(point) - plane.dist;
+            let children = node_children(node, n);
+            node = if d < 0.0 { children[1] } else { children[0] };
+        }
+
+        (-node - 1) as usize
+    }
+
+    /// Traces a ray through the world brushes, stopping at the first brush whose contents match `mask`
+    ///
+    /// Displacements and static props are not taken into account
+    pub fn trace_ray(&self, start: Vec3, end: Vec3, mask: BspContents) -> BspTrace {
+        self.trace_hull(start, end, Vec3::ZERO, Vec3::ZERO, mask)
+    }
+
+    /// Sweeps an axis-aligned box from `start` to `end` through the world brushes
+    ///
+    /// `mins` and `maxs` are relative to the trace position, like a player hull
+    pub fn trace_hull(
+        &self,
+        start: Vec3,
+        end: Vec3,
+        mins: Vec3,
+        maxs: Vec3,
+        mask: BspContents,
+    ) -> BspTrace {
+        // Sweep a box that is centered on the trace line
+        let offset = (mins + maxs) * 0.5;
+        let extents = (maxs - mins) * 0.5;
+        let mut work = TraceWork {
+            start: start + offset,
+            end: end + offset,
+            extents,
+            is_point: extents == Vec3::ZERO,
+            mask,
+            checked_brushes: vec![false; self.brushes.len()],
+            trace: BspTrace {
+                fraction: 1.0,
+                end_position: end,
+                start_solid: false,
+                all_solid: false,
+                plane: None,
+                tex_info: -1,
+                contents: BspContents::empty(),
+                brush: None,
+            },
+        };
+
+        let head_node = self.models.first().map(|m| m.head_node).unwrap_or(0);
+        let (trace_start, trace_end) = (work.start, work.end);
+        self.recursive_trace(&mut work, head_node, 0.0, 1.0, trace_start, trace_end);
+
+        work.trace.end_position = if work.trace.fraction == 1.0 {
+            end
+        } else {
+            start + (end - start) * work.trace.fraction
+        };
+
+        work.trace
+    }
+
+    fn recursive_trace(
+        &self,
+        w: &mut TraceWork,
+        node: i32,
+        p1f: f32,
+        p2f: f32,
+        p1: Vec3,
+        p2: Vec3,
+    ) {
+        if w.trace.fraction <= p1f {
+            return;
+        }
+
+        if node < 0 {
+            self.trace_to_leaf(w, (-node - 1) as usize);
+            return;
+        }
+
+        let Some(n) = self.nodes.get(node as usize) else {
+            return;
+        };
+        let Some(plane) = self.planes.get(n.plane_num as usize) else {
+            return;
+        };
+
+        let children = node_children(node, n);
+        let normal = Vec3::from(plane.normal);
+        let t1 = normal.dot(p1) - plane.dist;
+        let t2 = normal.dot(p2) - plane.dist;
+        let offset = if w.is_point {
+            0.0
+        } else {
+            (normal * w.extents).abs().element_sum()
+        };
+
+        if t1 >= offset && t2 >= offset {
+            self.recursive_trace(w, children[0], p1f, p2f, p1, p2);
+            return;
+        }
+        if t1 < -offset && t2 < -offset {
+            self.recursive_trace(w, children[1], p1f, p2f, p1, p2);
+            return;
+        }
+
+        // The segment crosses the plane, visit the near side first
+        let (side, frac1, frac2) = if t1 < t2 {
+            let idist = 1.0 / (t1 - t2);
+            (
+                1,
+                (t1 - offset + DIST_EPSILON) * idist,
+                (t1 + offset + DIST_EPSILON) * idist,
+            )
+        } else if t1 > t2 {
+            let idist = 1.0 / (t1 - t2);
+            (
+                0,
+                (t1 + offset + DIST_EPSILON) * idist,
+                (t1 - offset - DIST_EPSILON) * idist,
+            )
+        } else {
+            (0, 1.0, 0.0)
+        };
+
+        let frac1 = frac1.clamp(0.0, 1.0);
+        let frac2 = frac2.clamp(0.0, 1.0);
+
+        let midf = p1f + (p2f - p1f) * frac1;
+        let mid = p1 + (p2 - p1) * frac1;
+        self.recursive_trace(w, children[side], p1f, midf, p1, mid);
+
+        let midf = p1f + (p2f - p1f) * frac2;
+        let mid = p1 + (p2 - p1) * frac2;
+        self.recursive_trace(w, children[side ^ 1], midf, p2f, mid, p2);
+    }
+
+    fn trace_to_leaf(&self, w: &mut TraceWork, leaf: usize) {
+        let Some(leaf) = self.leafs.get(leaf) else {
+            return;
+        };
+
+        if !leaf.contents.intersects(w.mask) {
+            return;
+        }
+
+        let first = leaf.first_leaf_brush as usize;
+        for i in first..first + leaf.num_leaf_brushes as usize {
+            let Some(&brush_index) = self.leaf_brushes.get(i) else {
+                break;
+            };
+            let brush_index = brush_index as usize;
+            let Some(brush) = self.brushes.get(brush_index) else {
+                continue;
+            };
+
+            if std::mem::replace(&mut w.checked_brushes[brush_index], true) {
+                continue;
+            }
+
+            if !brush.contents.intersects(w.mask) {
+                continue;
+            }
+
+            self.clip_to_brush(w, brush, brush_index);
+            if w.trace.all_solid {
+                return;
+            }
+        }
+    }
+
+    fn clip_to_brush(&self, w: &mut TraceWork, brush: &BspBrush, brush_index: usize) {
+        if brush.num_sides <= 0 {
+            return;
+        }
+
+        let mut enter_frac = -1.0;
+        let mut leave_frac = 1.0;
+        let mut clip_side = None;
+        let mut get_out = false;
+        let mut start_out = false;
+
+        let first = brush.first_side as usize;
+        for side in self
+            .brush_sides
+            .iter()
+            .skip(first)
+            .take(brush.num_sides as usize)
+        {
+            // Bevels only exist to make box traces behave, they would produce false hits for rays
+            if w.is_point && side.bevel != 0 {
+                continue;
+            }
+
+            let Some(plane) = self.planes.get(side.plane_num as usize) else {
+                continue;
+            };
+
+            // Push the plane out by the box extents
+            let normal = Vec3::from(plane.normal);
+            let dist = plane.dist + (normal * w.extents).abs().element_sum();
+            let d1 = normal.dot(w.start) - dist;
+            let d2 = normal.dot(w.end) - dist;
+
+            if d2 > 0.0 {
+                get_out = true;
+            }
+            if d1 > 0.0 {
+                start_out = true;
+            }
+
+            // Completely in front of this face, so the brush can't be hit
+            if d1 > 0.0 && (d2 >= DIST_EPSILON || d2 >= d1) {
+                return;
+            }
+
+            // Completely behind this face
+            if d1 <= 0.0 && d2 <= 0.0 {
+                continue;
+            }
+
+            if d1 > d2 {
+                let f = ((d1 - DIST_EPSILON) / (d1 - d2)).max(0.0);
+                if f > enter_frac {
+                    enter_frac = f;
+                    clip_side = Some((side, plane));
+                }
+            } else {
+                let f = ((d1 + DIST_EPSILON) / (d1 - d2)).min(1.0);
+                if f < leave_frac {
+                    leave_frac = f;
+                }
+            }
+        }
+
+        if !start_out {
+            w.trace.start_solid = true;
+            if !get_out {
+                w.trace.all_solid = true;
+                w.trace.fraction = 0.0;
+                w.trace.contents = brush.contents;
+                w.trace.brush = Some(brush_index);
+            }
+            return;
+        }
+
+        if enter_frac > -1.0 && enter_frac < leave_frac && enter_frac < w.trace.fraction {
+            if let Some((side, plane)) = clip_side {
+                w.trace.fraction = enter_frac.max(0.0);
+                w.trace.plane = Some(plane.clone());
+                w.trace.tex_info = side.tex_info;
+                w.trace.contents = brush.contents;
+                w.trace.brush = Some(brush_index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lumps::{BspBrushSide, BspLeaf, BspModel, BspNode};
+    use binrw::BinReaderExt;
+    use std::io::Cursor;
+
+    fn plane(normal: [f32; 3], dist: f32) -> BspPlane {
+        BspPlane {
+            normal,
+            dist,
+            axis_type: 0,
+        }
+    }
+
+    fn leaf(contents: BspContents, first_leaf_brush: u16, num_leaf_brushes: u16) -> BspLeaf {
+        let mut data = contents.bits().to_le_bytes().to_vec();
+        data.extend([0; 20]);
+        data.extend(first_leaf_brush.to_le_bytes());
+        data.extend(num_leaf_brushes.to_le_bytes());
+        data.extend([0; 4]);
+        Cursor::new(data).read_le_args((1,)).unwrap()
+    }
+
+    /// A single node splitting the world at x = 0, with a 16 unit box brush from x = 16 to 32
+    fn box_world() -> Bsp {
+        let mut planes = vec![plane([1.0, 0.0, 0.0], 0.0)];
+        for axis in 0..3 {
+            let mut normal = [0.0; 3];
+            normal[axis] = 1.0;
+            let (min, max) = if axis == 0 { (16.0, 32.0) } else { (-8.0, 8.0) };
+            planes.push(plane(normal, max));
+            planes.push(plane(normal.map(|n| -n), -min));
+        }
+
+        Bsp {
+            brush_sides: (1..planes.len() as u16)
+                .map(|plane_num| BspBrushSide {
+                    plane_num,
+                    tex_info: plane_num as i16,
+                    disp_info: -1,
+                    bevel: 0,
+                    thin: 0,
+                })
+                .collect(),
+            planes,
+            models: vec![BspModel {
+                mins: [0.0; 3],
+                maxs: [0.0; 3],
+                origin: [0.0; 3],
+                head_node: 0,
+                first_face: 0,
+                num_faces: 0,
+            }],
+            nodes: vec![BspNode {
+                plane_num: 0,
+                children: [-2, -3],
+                mins: [0; 3],
+                maxs: [0; 3],
+                first_face: 0,
+                num_faces: 0,
+                area: 0,
+                padding: 0,
+            }],
+            leafs: vec![
+                leaf(BspContents::SOLID, 0, 0),
+                leaf(BspContents::SOLID, 0, 1),
+                leaf(BspContents::empty(), 0, 0),
+            ],
+            leaf_brushes: vec![0],
+            brushes: vec![BspBrush {
+                first_side: 0,
+                num_sides: 6,
+                contents: BspContents::SOLID,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn leaf_lookup() {
+        let mut bsp = box_world();
+        assert_eq!(bsp.find_leaf(Vec3::new(8.0, 0.0, 0.0)), 1);
+        assert_eq!(bsp.find_leaf(Vec3::new(-8.0, 0.0, 0.0)), 2);
+
+        // A node pointing back at itself ends up in the solid leaf instead of looping forever
+        bsp.nodes[0].children[1] = 0;
+        assert_eq!(bsp.find_leaf(Vec3::new(-8.0, 0.0, 0.0)), 0);
+        assert_eq!(
+            bsp.trace_ray(
+                Vec3::new(-64.0, 0.0, 0.0),
+                Vec3::new(-32.0, 0.0, 0.0),
+                BspContents::MASK_SOLID
+            )
+            .fraction,
+            1.0
+        );
+    }
+
+    #[test]
+    fn ray_traces() {
+        let bsp = box_world();
+        let mask = BspContents::MASK_SOLID;
+
+        let hit = bsp.trace_ray(Vec3::new(-64.0, 0.0, 0.0), Vec3::new(64.0, 0.0, 0.0), mask);
+        assert_eq!(hit.fraction, (80.0 - DIST_EPSILON) / 128.0);
+        assert_eq!(hit.plane.unwrap().normal, [-1.0, 0.0, 0.0]);
+        assert_eq!(hit.brush, Some(0));
+        assert!(!hit.start_solid);
+
+        let miss = bsp.trace_ray(
+            Vec3::new(-64.0, 16.0, 0.0),
+            Vec3::new(64.0, 16.0, 0.0),
+            mask,
+        );
+        assert_eq!(miss.fraction, 1.0);
+        assert_eq!(miss.end_position, Vec3::new(64.0, 16.0, 0.0));
+
+        let leaving = bsp.trace_ray(Vec3::new(24.0, 0.0, 0.0), Vec3::new(64.0, 0.0, 0.0), mask);
+        assert!(leaving.start_solid);
+        assert!(!leaving.all_solid);
+        assert_eq!(leaving.fraction, 1.0);
+
+        let inside = bsp.trace_ray(Vec3::new(20.0, 0.0, 0.0), Vec3::new(28.0, 0.0, 0.0), mask);
+        assert!(inside.start_solid);
+        assert!(inside.all_solid);
+        assert_eq!(inside.fraction, 0.0);
+        assert_eq!(inside.end_position, Vec3::new(20.0, 0.0, 0.0));
+
+        // Brushes that don't match the mask are ignored
+        let water = bsp.trace_ray(
+            Vec3::new(-64.0, 0.0, 0.0),
+            Vec3::new(64.0, 0.0, 0.0),
+            BspContents::MASK_WATER,
+        );
+        assert_eq!(water.fraction, 1.0);
+    }
+
+    #[test]
+    fn hull_traces() {
+        let mut bsp = box_world();
+        let (mins, maxs) = (Vec3::splat(-4.0), Vec3::splat(4.0));
+        let (start, end) = (Vec3::new(-64.0, 10.0, 0.0), Vec3::new(64.0, 10.0, 0.0));
+
+        // A ray passes beside the brush, but the box clips its edge
+        assert_eq!(
+            bsp.trace_ray(start, end, BspContents::MASK_SOLID).fraction,
+            1.0
+        );
+        let hit = bsp.trace_hull(start, end, mins, maxs, BspContents::MASK_SOLID);
+        assert_eq!(hit.fraction, (76.0 - DIST_EPSILON) / 128.0);
+        assert_eq!(hit.end_position.x, -64.0 + 128.0 * hit.fraction);
+        assert_eq!(hit.plane.unwrap().normal, [-1.0, 0.0, 0.0]);
+
+        // Off-center hulls are swept around their own center
+        let offset = bsp.trace_hull(
+            start,
+            end,
+            Vec3::new(-4.0, -4.0, 0.0),
+            Vec3::new(4.0, 4.0, 8.0),
+            BspContents::MASK_SOLID,
+        );
+        assert_eq!(offset.fraction, hit.fraction);
+
+        // Player clips only block player traces
+        bsp.brushes[0].contents = BspContents::PLAYERCLIP;
+        bsp.leafs[1] = leaf(BspContents::PLAYERCLIP, 0, 1);
+        assert_eq!(
+            bsp.trace_hull(start, end, mins, maxs, BspContents::MASK_SOLID)
+                .fraction,
+            1.0
+        );
+        assert_eq!(
+            bsp.trace_hull(start, end, mins, maxs, BspContents::MASK_PLAYERSOLID)
+                .fraction,
+            hit.fraction
+        );
+        assert_eq!(
+            bsp.trace_hull(start, end, mins, maxs, BspContents::MASK_NPCSOLID)
+                .fraction,
+            1.0
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use std::fmt::{self, Display};
+
+use crate::{gamelumps::DetailPropKind, lighting::LIGHT_STYLE_UNUSED, Bsp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BspSeverity {
+    /// The map loads, but something is likely wrong with it
+    Warning,
+    /// Using the data as-is will read out of bounds
+    Error,
+}
+
+/// Kinds of elements that can reference each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BspElement {
+    Plane,
+    Vertex,
+    Edge,
+    SurfEdge,
+    Face,
+    Model,
+    Node,
+    Leaf,
+    LeafFace,
+    LeafBrush,
+    Brush,
+    BrushSide,
+    TexInfo,
+    TexData,
+    TexDataString,
+    DispInfo,
+    DispVert,
+    Primitive,
+    PrimIndex,
+    Area,
+    AreaPortal,
+    Cluster,
+    ClipPortalVert,
+    DispTri,
+    StaticProp,
+    StaticPropModel,
+    StaticPropLeaf,
+    DetailProp,
+    DetailPropModel,
+    DetailPropSprite,
+}
+
+impl Display for BspElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BspElement::Plane => "plane",
+            BspElement::Vertex => "vertex",
+            BspElement::Edge => "edge",
+            BspElement::SurfEdge => "surfedge",
+            BspElement::Face => "face",
+            BspElement::Model => "model",
+            BspElement::Node => "node",
+            BspElement::Leaf => "leaf",
+            BspElement::LeafFace => "leaf face",
+            BspElement::LeafBrush => "leaf brush",
+            BspElement::Brush => "brush",
+            BspElement::BrushSide => "brush side",
+            BspElement::TexInfo => "texinfo",
+            BspElement::TexData => "texdata",
+            BspElement::TexDataString => "texdata string",
+            BspElement::DispInfo => "dispinfo",
+            BspElement::DispVert => "displacement vertex",
+            BspElement::Primitive => "primitive",
+            BspElement::PrimIndex => "primitive index",
+            BspElement::Area => "area",
+            BspElement::AreaPortal => "areaportal",
+            BspElement::Cluster => "visibility cluster",
+            BspElement::ClipPortalVert => "clip portal vertex",
+            BspElement::DispTri => "displacement triangle",
+            BspElement::StaticProp => "static prop",
+            BspElement::StaticPropModel => "static prop model",
+            BspElement::StaticPropLeaf => "static prop leaf",
+            BspElement::DetailProp => "detail prop",
+            BspElement::DetailPropModel => "detail prop model",
+            BspElement::DetailPropSprite => "detail prop sprite",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BspDiagnostic {
+    /// `source` references a single `target` element that doesn't exist
+    IndexOutOfRange {
+        source: BspElement,
+        source_index: usize,
+        target: BspElement,
+        index: i64,
+        target_count: usize,
+    },
+    /// `source` references a range of `target` elements that extends past the end of its lump
+    RangeOutOfRange {
+        source: BspElement,
+        source_index: usize,
+        target: BspElement,
+        first: i64,
+        count: i64,
+        target_count: usize,
+    },
+    /// A face with less than 3 edges
+    DegenerateFace { face: usize, num_edges: i16 },
+    /// Displacements can only be power 2, 3 or 4
+    InvalidDispPower { disp_info: usize, power: i32 },
+    /// The lightmap of a face extends past the end of the lighting lump
+    LightmapOutOfRange { face: usize, hdr: bool },
+    /// The models lump is empty, so there is no world to render or trace against
+    MissingWorldModel,
+    /// The HDR face lump doesn't have an entry for every face, so its lightmaps are ignored
+    HdrFaceCountMismatch { faces: usize, faces_hdr: usize },
+}
+
+impl BspDiagnostic {
+    pub fn severity(&self) -> BspSeverity {
+        match self {
+            BspDiagnostic::DegenerateFace { .. } | BspDiagnostic::HdrFaceCountMismatch { .. } => {
+                BspSeverity::Warning
+            }
+            _ => BspSeverity::Error,
+        }
+    }
+}
+
+impl Display for BspDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BspDiagnostic::IndexOutOfRange {
+                source,
+                source_index,
+                target,
+                index,
+                target_count,
+            } => write!(
+                f,
+                "{source} {source_index} references {target} {index}, but there are only {target_count}"
+            ),
+            BspDiagnostic::RangeOutOfRange {
+                source,
+                source_index,
+                target,
+                first,
+                count,
+                target_count,
+            } => write!(
+                f,
+                "{source} {source_index} references {count} {target}(s) starting at {first}, but there are only {target_count}"
+            ),
+            BspDiagnostic::DegenerateFace { face, num_edges } => {
+                write!(f, "face {face} only has {num_edges} edge(s)")
+            }
+            BspDiagnostic::InvalidDispPower { disp_info, power } => {
+                write!(f, "dispinfo {disp_info} has invalid power {power}")
+            }
+            BspDiagnostic::LightmapOutOfRange { face, hdr } => write!(
+                f,
+                "{} lightmap of face {face} extends past the end of the lighting lump",
+                if *hdr { "HDR" } else { "LDR" }
+            ),
+            BspDiagnostic::MissingWorldModel => write!(f, "map doesn't have a world model"),
+            BspDiagnostic::HdrFaceCountMismatch { faces, faces_hdr } => write!(
+                f,
+                "HDR face lump has {faces_hdr} face(s), but there are {faces}"
+            ),
+        }
+    }
+}
+
+struct Validator {
+    diagnostics: Vec<BspDiagnostic>,
+}
+
+impl Validator {
+    fn index(
+        &mut self,
+        source: BspElement,
+        source_index: usize,
+        target: BspElement,
+        index: i64,
+        target_count: usize,
+    ) {
+        let valid = index >= 0 && (index as usize) < target_count;
+        if !valid {
+            self.diagnostics.push(BspDiagnostic::IndexOutOfRange {
+                source,
+                source_index,
+                target,
+                index,
+                target_count,
+            });
+        }
+    }
+
+    fn range(
+        &mut self,
+        source: BspElement,
+        source_index: usize,
+        target: BspElement,
+        first: i64,
+        count: i64,
+        target_count: usize,
+    ) {
+        let valid = first >= 0 && count >= 0 && first + count <= target_count as i64;
+        if !valid {
+            self.diagnostics.push(BspDiagnostic::RangeOutOfRange {
+                source,
+                source_index,
+                target,
+                first,
+                count,
+                target_count,
+            });
+        }
+    }
+}
+
+impl Bsp {
+    /// Checks that all references between lumps are in bounds
+    ///
+    /// Any code that indexes into lumps directly should only be used on maps without errors
+    pub fn validate(&self) -> Vec<BspDiagnostic> {
+        use BspElement as E;
+
+        let mut v = Validator {
+            diagnostics: vec![],
+        };
+
+        for (i, &[v0, v1]) in self.edges.iter().enumerate() {
+            v.index(E::Edge, i, E::Vertex, v0 as i64, self.vertices.len());
+            v.index(E::Edge, i, E::Vertex, v1 as i64, self.vertices.len());
+        }
+
+        for (i, &edge) in self.surfedges.iter().enumerate() {
+            v.index(
+                E::SurfEdge,
+                i,
+                E::Edge,
+                edge.unsigned_abs() as i64,
+                self.edges.len(),
+            );
+        }
+
+        for (i, ti) in self.tex_info.iter().enumerate() {
+            // Faces without a material, eg. hint and skip brushes
+            if ti.tex_data != -1 {
+                v.index(
+                    E::TexInfo,
+                    i,
+                    E::TexData,
+                    ti.tex_data as i64,
+                    self.tex_data.len(),
+                );
+            }
+        }
+
+        for (i, td) in self.tex_data.iter().enumerate() {
+            v.index(
+                E::TexData,
+                i,
+                E::TexDataString,
+                td.name_index as i64,
+                self.texdata_string_table.len(),
+            );
+        }
+
+        for (i, face) in self.faces.iter().enumerate() {
+            v.index(
+                E::Face,
+                i,
+                E::Plane,
+                face.plane_num as i64,
+                self.planes.len(),
+            );
+            if face.num_edges < 3 {
+                v.diagnostics.push(BspDiagnostic::DegenerateFace {
+                    face: i,
+                    num_edges: face.num_edges,
+                });
+            }
+            v.range(
+                E::Face,
+                i,
+                E::SurfEdge,
+                face.first_edge as i64,
+                face.num_edges as i64,
+                self.surfedges.len(),
+            );
+            if face.tex_info != -1 {
+                v.index(
+                    E::Face,
+                    i,
+                    E::TexInfo,
+                    face.tex_info as i64,
+                    self.tex_info.len(),
+                );
+            }
+            if face.disp_info != -1 {
+                v.index(
+                    E::Face,
+                    i,
+                    E::DispInfo,
+                    face.disp_info as i64,
+                    self.disp_info.len(),
+                );
+            }
+            v.range(
+                E::Face,
+                i,
+                E::Primitive,
+                face.first_primitive as i64,
+                face.primitive_count() as i64,
+                self.primitives.len(),
+            );
+        }
+
+        for (i, primitive) in self.primitives.iter().enumerate() {
+            v.range(
+                E::Primitive,
+                i,
+                E::PrimIndex,
+                primitive.first_index as i64,
+                primitive.index_count as i64,
+                self.prim_indices.len(),
+            );
+        }
+
+        if !self.faces_hdr.is_empty() && self.faces_hdr.len() != self.faces.len() {
+            v.diagnostics.push(BspDiagnostic::HdrFaceCountMismatch {
+                faces: self.faces.len(),
+                faces_hdr: self.faces_hdr.len(),
+            });
+        }
+
+        for hdr in [false, true] {
+            // Maps without lighting have stale offsets
+            if self.lightmap_samples(hdr).is_empty() {
+                continue;
+            }
+
+            for (i, face) in self.lightmap_faces(hdr).iter().enumerate() {
+                let lit = face.lightmap_data_offset >= 0 && face.styles[0] != LIGHT_STYLE_UNUSED;
+                if lit && self.face_lightmap(i, hdr).is_none() {
+                    v.diagnostics
+                        .push(BspDiagnostic::LightmapOutOfRange { face: i, hdr });
+                }
+            }
+        }
+
+        for (i, disp) in self.disp_info.iter().enumerate() {
+            v.index(
+                E::DispInfo,
+                i,
+                E::Face,
+                disp.map_face as i64,
+                self.faces.len(),
+            );
+            if !(2..=4).contains(&disp.power) {
+                v.diagnostics.push(BspDiagnostic::InvalidDispPower {
+                    disp_info: i,
+                    power: disp.power,
+                });
+                continue;
+            }
+
+            let size = (1i64 << disp.power) + 1;
+            v.range(
+                E::DispInfo,
+                i,
+                E::DispVert,
+                disp.disp_vert_start as i64,
+                size * size,
+                self.disp_verts.len(),
+            );
+            v.range(
+                E::DispInfo,
+                i,
+                E::DispTri,
+                disp.disp_tri_start as i64,
+                2 * (size - 1) * (size - 1),
+                self.disp_tris.len(),
+            );
+        }
+
+        if self.models.is_empty() {
+            v.diagnostics.push(BspDiagnostic::MissingWorldModel);
+        }
+        for (i, model) in self.models.iter().enumerate() {
+            v.range(
+                E::Model,
+                i,
+                E::Face,
+                model.first_face as i64,
+                model.num_faces as i64,
+                self.faces.len(),
+            );
+            self.validate_child(&mut v, E::Model, i, model.head_node);
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            v.index(
+                E::Node,
+                i,
+                E::Plane,
+                node.plane_num as i64,
+                self.planes.len(),
+            );
+            v.range(
+                E::Node,
+                i,
+                E::Face,
+                node.first_face as i64,
+                node.num_faces as i64,
+                self.faces.len(),
+            );
+            for child in node.children {
+                self.validate_child(&mut v, E::Node, i, child);
+            }
+        }
+
+        for (i, leaf) in self.leafs.iter().enumerate() {
+            v.range(
+                E::Leaf,
+                i,
+                E::LeafFace,
+                leaf.first_leaf_face as i64,
+                leaf.num_leaf_faces as i64,
+                self.leaf_faces.len(),
+            );
+            v.range(
+                E::Leaf,
+                i,
+                E::LeafBrush,
+                leaf.first_leaf_brush as i64,
+                leaf.num_leaf_brushes as i64,
+                self.leaf_brushes.len(),
+            );
+            v.index(E::Leaf, i, E::Area, leaf.area() as i64, self.areas.len());
+            // Solid leafs don't have a cluster, and maps without vis put everything in cluster 0
+            if let (Some(vis), 0..) = (&self.visibility, leaf.cluster) {
+                v.index(
+                    E::Leaf,
+                    i,
+                    E::Cluster,
+                    leaf.cluster as i64,
+                    vis.num_clusters(),
+                );
+            }
+        }
+
+        for (i, &face) in self.leaf_faces.iter().enumerate() {
+            v.index(E::LeafFace, i, E::Face, face as i64, self.faces.len());
+        }
+
+        for (i, &brush) in self.leaf_brushes.iter().enumerate() {
+            v.index(E::LeafBrush, i, E::Brush, brush as i64, self.brushes.len());
+        }
+
+        for (i, brush) in self.brushes.iter().enumerate() {
+            v.range(
+                E::Brush,
+                i,
+                E::BrushSide,
+                brush.first_side as i64,
+                brush.num_sides as i64,
+                self.brush_sides.len(),
+            );
+        }
+
+        for (i, side) in self.brush_sides.iter().enumerate() {
+            v.index(
+                E::BrushSide,
+                i,
+                E::Plane,
+                side.plane_num as i64,
+                self.planes.len(),
+            );
+            if side.tex_info != -1 {
+                v.index(
+                    E::BrushSide,
+                    i,
+                    E::TexInfo,
+                    side.tex_info as i64,
+                    self.tex_info.len(),
+                );
+            }
+        }
+
+        for (i, area) in self.areas.iter().enumerate() {
+            v.range(
+                E::Area,
+                i,
+                E::AreaPortal,
+                area.first_area_portal as i64,
+                area.num_area_portals as i64,
+                self.area_portals.len(),
+            );
+        }
+
+        for (i, portal) in self.area_portals.iter().enumerate() {
+            v.index(
+                E::AreaPortal,
+                i,
+                E::Area,
+                portal.other_area as i64,
+                self.areas.len(),
+            );
+            v.range(
+                E::AreaPortal,
+                i,
+                E::ClipPortalVert,
+                portal.first_clip_portal_vert as i64,
+                portal.clip_portal_verts as i64,
+                self.clip_portal_verts.len(),
+            );
+        }
+
+        for (i, prop) in self.static_props.iter().enumerate() {
+            v.index(
+                E::StaticProp,
+                i,
+                E::StaticPropModel,
+                prop.model_index as i64,
+                self.static_prop_models.len(),
+            );
+            v.range(
+                E::StaticProp,
+                i,
+                E::StaticPropLeaf,
+                prop.first_leaf as i64,
+                prop.leaf_count as i64,
+                self.static_prop_leafs.len(),
+            );
+        }
+
+        for (i, &leaf) in self.static_prop_leafs.iter().enumerate() {
+            v.index(E::StaticPropLeaf, i, E::Leaf, leaf as i64, self.leafs.len());
+        }
+
+        for (i, prop) in self.detail_props.iter().enumerate() {
+            match prop.kind() {
+                DetailPropKind::Model => v.index(
+                    E::DetailProp,
+                    i,
+                    E::DetailPropModel,
+                    prop.detail_model as i64,
+                    self.detail_prop_models.len(),
+                ),
+                DetailPropKind::Unknown(_) => {}
+                _ => v.index(
+                    E::DetailProp,
+                    i,
+                    E::DetailPropSprite,
+                    prop.detail_model as i64,
+                    self.detail_prop_sprites.len(),
+                ),
+            }
+        }
+
+        v.diagnostics
+    }
+
+    /// Node children are either nodes, or leafs encoded as `-(leaf + 1)`
+    fn validate_child(&self, v: &mut Validator, source: BspElement, index: usize, child: i32) {
+        if child >= 0 {
+            v.index(
+                source,
+                index,
+                BspElement::Node,
+                child as i64,
+                self.nodes.len(),
+            );
+        } else {
+            v.index(
+                source,
+                index,
+                BspElement::Leaf,
+                -(child as i64) - 1,
+                self.leafs.len(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lumps::{BspArea, BspFace, BspLeaf, BspModel},
+        visibility::BspVisibility,
+    };
+    use binrw::Endian;
+
+    fn face() -> BspFace {
+        BspFace {
+            plane_num: 0,
+            side: 0,
+            on_node: 0,
+            first_edge: 1,
+            num_edges: 3,
+            tex_info: -1,
+            disp_info: -1,
+            surface_fog_volume_id: -1,
+            styles: [LIGHT_STYLE_UNUSED; 4],
+            lightmap_data_offset: -1,
+            area: 0.0,
+            lightmap_mins: [0; 2],
+            lightmap_size: [0; 2],
+            orig_face: -1,
+            num_primitives: 0,
+            first_primitive: 0,
+            smoothing_groups: 0,
+        }
+    }
+
+    #[test]
+    fn reports_broken_references() {
+        let bsp = Bsp {
+            vertices: vec![[0.0; 3]; 3],
+            edges: vec![[0, 1], [1, 2], [2, 3]],
+            surfedges: vec![0, 1, -2],
+            faces: vec![face()],
+            ..Default::default()
+        };
+
+        let diagnostics = bsp.validate();
+        assert_eq!(
+            diagnostics,
+            [
+                BspDiagnostic::IndexOutOfRange {
+                    source: BspElement::Edge,
+                    source_index: 2,
+                    target: BspElement::Vertex,
+                    index: 3,
+                    target_count: 3,
+                },
+                BspDiagnostic::IndexOutOfRange {
+                    source: BspElement::Face,
+                    source_index: 0,
+                    target: BspElement::Plane,
+                    index: 0,
+                    target_count: 0,
+                },
+                BspDiagnostic::RangeOutOfRange {
+                    source: BspElement::Face,
+                    source_index: 0,
+                    target: BspElement::SurfEdge,
+                    first: 1,
+                    count: 3,
+                    target_count: 3,
+                },
+                BspDiagnostic::MissingWorldModel,
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "edge 2 references vertex 3, but there are only 3"
+        );
+    }
+
+    #[test]
+    fn reports_lump_mismatches() {
+        let leaf = |cluster, area| BspLeaf {
+            cluster,
+            area_flags: area,
+            ..Default::default()
+        };
+        let mut visibility = 2u32.to_le_bytes().to_vec();
+        visibility.extend([0; 16]);
+
+        let bsp = Bsp {
+            faces_hdr: vec![face()],
+            leafs: vec![leaf(-1, 0), leaf(1, 0), leaf(2, 1)],
+            areas: vec![BspArea {
+                num_area_portals: 0,
+                first_area_portal: 0,
+            }],
+            visibility: Some(BspVisibility::parse(visibility, Endian::Little).unwrap()),
+            models: vec![BspModel {
+                mins: [0.0; 3],
+                maxs: [0.0; 3],
+                origin: [0.0; 3],
+                head_node: -1,
+                first_face: 0,
+                num_faces: 0,
+            }],
+            ..Default::default()
+        };
+
+        let diagnostics = bsp.validate();
+        assert_eq!(
+            diagnostics,
+            [
+                BspDiagnostic::HdrFaceCountMismatch {
+                    faces: 0,
+                    faces_hdr: 1,
+                },
+                BspDiagnostic::IndexOutOfRange {
+                    source: BspElement::Leaf,
+                    source_index: 2,
+                    target: BspElement::Area,
+                    index: 1,
+                    target_count: 1,
+                },
+                BspDiagnostic::IndexOutOfRange {
+                    source: BspElement::Leaf,
+                    source_index: 2,
+                    target: BspElement::Cluster,
+                    index: 2,
+                    target_count: 2,
+                },
+            ]
+        );
+        assert_eq!(diagnostics[0].severity(), BspSeverity::Warning);
+    }
+}
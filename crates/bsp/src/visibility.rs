@@ -0,0 +1,182 @@
+use binrw::{BinReaderExt, Endian};
+use eyre::ensure;
+use std::io::Cursor;
+
+const DVIS_PVS: usize = 0;
+const DVIS_PAS: usize = 1;
+
+/// Potentially visible/audible sets, run-length encoded per cluster
+#[derive(Debug, Clone)]
+pub struct BspVisibility {
+    /// PVS and PAS byte offsets for every cluster, relative to the start of the lump
+    pub offsets: Vec<[u32; 2]>,
+    data: Vec<u8>,
+}
+
+impl BspVisibility {
+    pub fn parse(data: Vec<u8>, endian: Endian) -> eyre::Result<Self> {
+        let mut c = Cursor::new(&data);
+        let num_clusters: u32 = c.read_type(endian)?;
+        ensure!(
+            4 + num_clusters as usize * 8 <= data.len(),
+            "Visibility lump is too small for {num_clusters} clusters"
+        );
+
+        let mut offsets = Vec::with_capacity(num_clusters as usize);
+        for _ in 0..num_clusters {
+            offsets.push(c.read_type(endian)?);
+        }
+
+        Ok(Self { offsets, data })
+    }
+
+    pub fn num_clusters(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Size in bytes of a single decompressed cluster bit vector
+    pub fn row_size(&self) -> usize {
+        self.num_clusters().div_ceil(8)
+    }
+
+    /// Decompresses the PVS bit vector for `cluster`
+    pub fn pvs(&self, cluster: usize) -> Option<Vec<u8>> {
+        self.decompress(cluster, DVIS_PVS)
+    }
+
+    /// Decompresses the PAS bit vector for `cluster`
+    pub fn pas(&self, cluster: usize) -> Option<Vec<u8>> {
+        self.decompress(cluster, DVIS_PAS)
+    }
+
+    pub fn is_cluster_visible(&self, from: usize, to: usize) -> bool {
+        self.test_bit(from, to, DVIS_PVS)
+    }
+
+    pub fn is_cluster_audible(&self, from: usize, to: usize) -> bool {
+        self.test_bit(from, to, DVIS_PAS)
+    }
+
+    /// Returns all clusters in the PVS of `from`
+    pub fn visible_clusters(&self, from: usize) -> Vec<usize> {
+        self.pvs(from)
+            .map(|row| self.set_bits(&row))
+            .unwrap_or_default()
+    }
+
+    /// Returns all clusters in the PAS of `from`
+    pub fn audible_clusters(&self, from: usize) -> Vec<usize> {
+        self.pas(from)
+            .map(|row| self.set_bits(&row))
+            .unwrap_or_default()
+    }
+
+    fn set_bits(&self, row: &[u8]) -> Vec<usize> {
+        (0..self.num_clusters())
+            .filter(|&c| row[c >> 3] & (1 << (c & 7)) != 0)
+            .collect()
+    }
+
+    fn compressed(&self, cluster: usize, kind: usize) -> Option<&[u8]> {
+        let offset = self.offsets.get(cluster)?[kind] as usize;
+        self.data.get(offset..)
+    }
+
+    fn decompress(&self, cluster: usize, kind: usize) -> Option<Vec<u8>> {
+        let compressed = self.compressed(cluster, kind)?;
+        Some(decompress_row(compressed, self.row_size()))
+    }
+
+    /// Walks the compressed row up to the byte containing `to`, without decompressing the whole row
+    fn test_bit(&self, from: usize, to: usize, kind: usize) -> bool {
+        if to >= self.num_clusters() {
+            return false;
+        }
+
+        let Some(compressed) = self.compressed(from, kind) else {
+            return false;
+        };
+
+        let target = to >> 3;
+        let mut out = 0;
+        let mut i = 0;
+        while out <= target && i < compressed.len() {
+            if compressed[i] != 0 {
+                if out == target {
+                    return compressed[i] & (1 << (to & 7)) != 0;
+                }
+                out += 1;
+                i += 1;
+            } else {
+                let run = compressed.get(i + 1).copied().unwrap_or(0) as usize;
+                if out + run > target {
+                    return false;
+                }
+                out += run;
+                i += 2;
+            }
+        }
+
+        false
+    }
+}
+
+/// Zero bytes are followed by a repeat count, all other bytes are copied as-is
+fn decompress_row(compressed: &[u8], row_size: usize) -> Vec<u8> {
+    let mut row = Vec::with_capacity(row_size);
+    let mut i = 0;
+    while row.len() < row_size && i < compressed.len() {
+        if compressed[i] != 0 {
+            row.push(compressed[i]);
+            i += 1;
+        } else {
+            let run = compressed.get(i + 1).copied().unwrap_or(0) as usize;
+            let run = run.min(row_size - row.len());
+            row.resize(row.len() + run, 0);
+            i += 2;
+        }
+    }
+
+    row.resize(row_size, 0);
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_vis() -> BspVisibility {
+        // 20 clusters (3 byte rows)
+        let mut data = vec![];
+        data.extend_from_slice(&20u32.to_le_bytes());
+        for _ in 0..20 {
+            data.extend_from_slice(&(4u32 + 20 * 8).to_le_bytes());
+            data.extend_from_slice(&(4u32 + 20 * 8 + 4).to_le_bytes());
+        }
+        // PVS: clusters 0, 1 and 17, with a run of one zero byte
+        data.extend_from_slice(&[0b0000_0011, 0, 1, 0b0000_0010]);
+        // PAS: everything
+        data.extend_from_slice(&[0xFF, 0xFF, 0x0F]);
+        BspVisibility::parse(data, Endian::Little).unwrap()
+    }
+
+    #[test]
+    fn decompress_rle() {
+        let vis = test_vis();
+        assert_eq!(vis.row_size(), 3);
+        assert_eq!(vis.pvs(0).unwrap(), vec![0b0000_0011, 0, 0b0000_0010]);
+        assert_eq!(vis.visible_clusters(5), vec![0, 1, 17]);
+        assert_eq!(vis.audible_clusters(5), (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cluster_queries() {
+        let vis = test_vis();
+        assert!(vis.is_cluster_visible(3, 1));
+        assert!(vis.is_cluster_visible(3, 17));
+        assert!(!vis.is_cluster_visible(3, 8));
+        assert!(!vis.is_cluster_visible(3, 16));
+        assert!(!vis.is_cluster_visible(3, 20));
+        assert!(vis.is_cluster_audible(3, 19));
+    }
+}
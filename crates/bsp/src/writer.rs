@@ -0,0 +1,432 @@
+use binrw::{BinWrite, BinWriterExt, Endian};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    lumps::BspGameLump, profile::BspLumpLayout, Bsp, BspFile, BspHeader, BspLump, BspLumpReader,
+    BSP_LUMP_COUNT,
+};
+
+pub const LUMP_GAME_LUMP: usize = 35;
+pub const LUMP_PAKFILE: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BspCompression {
+    #[default]
+    None,
+    /// Compresses every lump except the pakfile with LZMA, like `bspzip -repack -compress`
+    Lzma,
+}
+
+/// Decompressed contents of every lump in a BSP file
+#[derive(Debug, Clone)]
+pub struct BspLumps {
+    pub version: i32,
+    pub map_revision: i32,
+    pub lump_layout: BspLumpLayout,
+    /// Indexed by lump number. The game lump entry is ignored, see `game_lumps`
+    pub lumps: Vec<BspLumpData>,
+    pub game_lumps: Vec<BspGameLumpData>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BspLumpData {
+    pub version: i32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BspGameLumpData {
+    pub id: u32,
+    pub flags: u16,
+    pub version: u16,
+    pub data: Vec<u8>,
+}
+
+impl<R: Read + Seek> BspFile<R> {
+    /// Reads and decompresses every lump, including the individual game lumps
+    pub fn read_lumps(&mut self) -> eyre::Result<BspLumps> {
+        eyre::ensure!(
+            self.header.endian == Endian::Little,
+            "Rewriting big-endian maps is not supported"
+        );
+
+        let mut lumps = Vec::with_capacity(BSP_LUMP_COUNT);
+        for index in 0..BSP_LUMP_COUNT {
+            let version = self.header.lumps[index].version;
+            let data = if index == LUMP_GAME_LUMP {
+                vec![]
+            } else {
+                self.read_lump_raw(index)?
+            };
+            lumps.push(BspLumpData { version, data });
+        }
+
+        let mut game_lumps = vec![];
+        for gl in self.game_lumps()? {
+            // Compressed maps end the directory with an empty entry
+            if gl.id == 0 {
+                continue;
+            }
+
+            game_lumps.push(BspGameLumpData {
+                id: gl.id,
+                flags: gl.flags & !1,
+                version: gl.version,
+                data: self.read_lump_raw_offset(gl.fileofs as u64, gl.filelen as usize)?,
+            });
+        }
+
+        Ok(BspLumps {
+            version: self.header.version,
+            map_revision: self.header.map_revision,
+            lump_layout: self.header.lump_layout,
+            lumps,
+            game_lumps,
+        })
+    }
+
+    /// Writes a copy of this file, with every lump decompressed or recompressed
+    pub fn write<W: Write + Seek>(
+        &mut self,
+        writer: W,
+        compression: BspCompression,
+    ) -> eyre::Result<()> {
+        self.read_lumps()?.write(writer, compression)
+    }
+}
+
+impl BspLumps {
+    /// Creates a file with every lump empty, in the standard layout
+    pub fn new(version: i32, map_revision: i32) -> Self {
+        BspLumps {
+            version,
+            map_revision,
+            lump_layout: BspLumpLayout::Standard,
+            lumps: vec![BspLumpData::default(); BSP_LUMP_COUNT],
+            game_lumps: vec![],
+        }
+    }
+
+    pub fn game_lump(&self, id: u32) -> Option<&BspGameLumpData> {
+        self.game_lumps.iter().find(|l| l.id == id)
+    }
+
+    pub fn game_lump_mut(&mut self, id: u32) -> Option<&mut BspGameLumpData> {
+        self.game_lumps.iter_mut().find(|l| l.id == id)
+    }
+
+    /// Replaces the data of a lump, keeping its version
+    pub fn set_lump(&mut self, index: usize, data: Vec<u8>) {
+        self.lumps[index].data = data;
+    }
+
+    /// Writes a complete BSP file. Offsets are relative to the current position of `writer`
+    pub fn write<W: Write + Seek>(
+        &self,
+        mut writer: W,
+        compression: BspCompression,
+    ) -> eyre::Result<()> {
+        eyre::ensure!(
+            self.lumps.len() == BSP_LUMP_COUNT,
+            "Expected {BSP_LUMP_COUNT} lumps, got {}",
+            self.lumps.len()
+        );
+
+        let start = writer.stream_position()?;
+        let mut header = BspHeader {
+            version: self.version,
+            lumps: vec![
+                BspLump {
+                    offset: 0,
+                    length: 0,
+                    version: 0,
+                    fourcc: [0; 4],
+                };
+                BSP_LUMP_COUNT
+            ],
+            map_revision: self.map_revision,
+            endian: Endian::Little,
+            lump_layout: self.lump_layout,
+        };
+
+        // Reserve space for the header, it's written once all the offsets are known
+        header.write_le(&mut writer)?;
+
+        for (index, lump) in self.lumps.iter().enumerate() {
+            align(&mut writer, start)?;
+            let offset = writer.stream_position()? - start;
+
+            let mut fourcc = [0; 4];
+            if index == LUMP_GAME_LUMP {
+                self.write_game_lumps(&mut writer, start, compression)?;
+            } else if compression == BspCompression::Lzma && index != LUMP_PAKFILE {
+                match compress_lump(&lump.data)? {
+                    Some(compressed) => {
+                        writer.write_all(&compressed)?;
+                        fourcc = (lump.data.len() as u32).to_le_bytes();
+                    }
+                    None => writer.write_all(&lump.data)?,
+                }
+            } else {
+                writer.write_all(&lump.data)?;
+            }
+
+            let length = writer.stream_position()? - start - offset;
+            header.lumps[index] = BspLump {
+                offset: if length == 0 { 0 } else { offset as u32 },
+                length: length as u32,
+                version: lump.version,
+                fourcc,
+            };
+        }
+
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(start))?;
+        header.write_le(&mut writer)?;
+        writer.seek(SeekFrom::Start(end))?;
+
+        Ok(())
+    }
+
+    fn write_game_lumps<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        start: u64,
+        compression: BspCompression,
+    ) -> eyre::Result<()> {
+        let mut payloads = Vec::with_capacity(self.game_lumps.len());
+        for gl in &self.game_lumps {
+            let compressed = if compression == BspCompression::Lzma {
+                compress_lump(&gl.data)?
+            } else {
+                None
+            };
+            payloads.push(compressed);
+        }
+
+        // Compressed game lumps are sized using the offset of the next entry, so an empty one is added at the end
+        let entry_count = self.game_lumps.len() + (compression == BspCompression::Lzma) as usize;
+        let directory_size = 4 + entry_count as u64 * 16;
+        let mut data_offset = writer.stream_position()? - start + directory_size;
+
+        let mut directory = Vec::with_capacity(entry_count);
+        for (gl, compressed) in self.game_lumps.iter().zip(&payloads) {
+            let (flags, length) = match compressed {
+                Some(c) => (gl.flags | 1, c.len()),
+                None => (gl.flags & !1, gl.data.len()),
+            };
+            directory.push(BspGameLump {
+                id: gl.id,
+                flags,
+                version: gl.version,
+                fileofs: data_offset as u32,
+                filelen: length as u32,
+            });
+            data_offset += length as u64;
+        }
+
+        if compression == BspCompression::Lzma {
+            directory.push(BspGameLump {
+                id: 0,
+                flags: 0,
+                version: 0,
+                fileofs: data_offset as u32,
+                filelen: 0,
+            });
+        }
+
+        writer.write_le(&(directory.len() as u32))?;
+        for entry in &directory {
+            writer.write_le(entry)?;
+        }
+
+        for (gl, compressed) in self.game_lumps.iter().zip(&payloads) {
+            writer.write_all(compressed.as_deref().unwrap_or(&gl.data))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Bsp {
+    /// Serializes the parsed lumps back into `lumps`
+    ///
+    /// Lumps that aren't fully represented by this struct (visibility, game lumps, pakfile, etc.) are left untouched
+    pub fn store_lumps(&self, lumps: &mut BspLumps) -> eyre::Result<()> {
+        let mut entities = self.entities.clone().into_bytes();
+        entities.push(0);
+        lumps.set_lump(0, entities);
+
+        lumps.set_lump(1, write_lump(&self.planes)?);
+        lumps.set_lump(2, write_lump(&self.tex_data)?);
+        lumps.set_lump(3, write_lump(&self.vertices)?);
+        lumps.set_lump(5, write_lump(&self.nodes)?);
+        lumps.set_lump(6, write_lump(&self.tex_info)?);
+        lumps.set_lump(7, write_lump(&self.faces)?);
+        lumps.set_lump(8, write_lump(&self.lightmap_data)?);
+        let mut occluders = Cursor::new(vec![]);
+        occluders.write_le(&self.occluders)?;
+        lumps.set_lump(9, occluders.into_inner());
+        lumps.set_lump(10, write_lump(&self.leafs)?);
+        lumps.set_lump(12, write_lump(&self.edges)?);
+        lumps.set_lump(13, write_lump(&self.surfedges)?);
+        lumps.set_lump(14, write_lump(&self.models)?);
+        lumps.set_lump(15, write_lump(&self.world_lights)?);
+        lumps.set_lump(16, write_lump(&self.leaf_faces)?);
+        lumps.set_lump(17, write_lump(&self.leaf_brushes)?);
+        lumps.set_lump(18, write_lump(&self.brushes)?);
+        lumps.set_lump(19, write_lump(&self.brush_sides)?);
+        lumps.set_lump(20, write_lump(&self.areas)?);
+        lumps.set_lump(21, write_lump(&self.area_portals)?);
+        lumps.set_lump(26, write_lump(&self.disp_info)?);
+        lumps.set_lump(33, write_lump(&self.disp_verts)?);
+        lumps.set_lump(37, write_lump(&self.primitives)?);
+        lumps.set_lump(38, write_lump(&self.prim_verts)?);
+        lumps.set_lump(39, write_lump(&self.prim_indices)?);
+        lumps.set_lump(41, write_lump(&self.clip_portal_verts)?);
+        lumps.set_lump(42, write_lump(&self.cubemaps)?);
+        lumps.set_lump(45, write_lump(&self.overlays)?);
+        lumps.set_lump(48, write_lump(&self.disp_tris)?);
+        lumps.set_lump(50, write_lump(&self.water_overlays)?);
+        lumps.set_lump(51, write_lump(&self.leaf_ambient_index_hdr)?);
+        lumps.set_lump(52, write_lump(&self.leaf_ambient_index)?);
+        lumps.set_lump(53, write_lump(&self.lightmap_data_hdr)?);
+        lumps.set_lump(54, write_lump(&self.world_lights_hdr)?);
+        lumps.set_lump(55, write_lump(&self.leaf_ambient_lighting_hdr)?);
+        lumps.set_lump(56, write_lump(&self.leaf_ambient_lighting)?);
+        lumps.set_lump(58, write_lump(&self.faces_hdr)?);
+        lumps.set_lump(60, write_lump(&self.overlay_fades)?);
+
+        let mut string_data = vec![];
+        let mut string_offsets = Vec::with_capacity(self.texdata_string_table.len());
+        for s in &self.texdata_string_table {
+            string_offsets.push(string_data.len() as u32);
+            string_data.extend_from_slice(s.as_bytes());
+            string_data.push(0);
+        }
+        lumps.set_lump(43, string_data);
+        lumps.set_lump(44, write_lump(&string_offsets)?);
+
+        Ok(())
+    }
+
+    /// Writes a new BSP file containing this struct's lumps, taking every other lump from `source`
+    pub fn write_to<R: Read + Seek, W: Write + Seek>(
+        &self,
+        source: &mut BspFile<R>,
+        writer: W,
+        compression: BspCompression,
+    ) -> eyre::Result<()> {
+        let mut lumps = source.read_lumps()?;
+        self.store_lumps(&mut lumps)?;
+        lumps.write(writer, compression)
+    }
+}
+
+pub(crate) fn write_lump<T>(items: &[T]) -> eyre::Result<Vec<u8>>
+where
+    T: BinWrite,
+    for<'a> T::Args<'a>: Default,
+{
+    let mut c = Cursor::new(vec![]);
+    for item in items {
+        c.write_le(item)?;
+    }
+
+    Ok(c.into_inner())
+}
+
+fn align<W: Write + Seek>(writer: &mut W, start: u64) -> eyre::Result<()> {
+    let pos = writer.stream_position()? - start;
+    let padding = pos.next_multiple_of(4) - pos;
+    writer.write_all(&[0u8; 4][..padding as usize])?;
+    Ok(())
+}
+
+/// Compresses `data` into Valve's LZMA lump format. Returns `None` if compressing doesn't save any space
+pub(crate) fn compress_lump(data: &[u8]) -> eyre::Result<Option<Vec<u8>>> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    let (properties, compressed) = compress_lzma(data)?;
+
+    // id, actual size, lzma size and properties
+    const LZMA_HEADER_SIZE: usize = 17;
+    if compressed.len() + LZMA_HEADER_SIZE >= data.len() {
+        return Ok(None);
+    }
+
+    let mut out = Vec::with_capacity(compressed.len() + LZMA_HEADER_SIZE);
+    out.extend_from_slice(b"LZMA");
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    out.extend_from_slice(&properties);
+    out.extend_from_slice(&compressed);
+    Ok(Some(out))
+}
+
+/// Compresses `data` into a raw LZMA stream without an end marker, returning the stream properties alongside it
+pub(crate) fn compress_lzma(data: &[u8]) -> eyre::Result<([u8; 5], Vec<u8>)> {
+    let options = lzma_rust2::LzmaOptions::default();
+    let mut encoder =
+        lzma_rust2::LzmaWriter::new(vec![], &options, false, false, Some(data.len() as u64))?;
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    let mut properties = [options.get_props(), 0, 0, 0, 0];
+    properties[1..].copy_from_slice(&options.dict_size.to_le_bytes());
+    Ok((properties, compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_lumps() -> BspLumps {
+        let mut lumps = BspLumps::new(20, 42);
+        lumps.game_lumps.push(BspGameLumpData {
+            id: u32::from_be_bytes(*b"sprp"),
+            flags: 0,
+            version: 10,
+            data: b"static props".repeat(100),
+        });
+        lumps.set_lump(0, b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec());
+        lumps.set_lump(3, write_lump(&[[1.0f32, 2.0, 3.0]; 64]).unwrap());
+        lumps.set_lump(LUMP_PAKFILE, vec![7; 13]);
+        lumps.lumps[10].version = 1;
+        lumps
+    }
+
+    fn roundtrip(compression: BspCompression) {
+        let lumps = test_lumps();
+        let mut out = Cursor::new(vec![]);
+        lumps.write(&mut out, compression).unwrap();
+
+        out.set_position(0);
+        let mut file = BspFile::new(out).unwrap();
+        assert_eq!(file.header.map_revision, 42);
+        if compression == BspCompression::Lzma {
+            assert_eq!(file.header.lumps[3].fourcc, (64u32 * 12).to_le_bytes());
+        }
+
+        let read = file.read_lumps().unwrap();
+        assert_eq!(read.version, 20);
+        assert_eq!(read.lumps[10].version, 1);
+        for (a, b) in lumps.lumps.iter().zip(&read.lumps) {
+            assert_eq!(a.data, b.data);
+        }
+        assert_eq!(read.game_lumps.len(), 1);
+        assert_eq!(read.game_lumps[0].data, lumps.game_lumps[0].data);
+    }
+
+    #[test]
+    fn write_uncompressed() {
+        roundtrip(BspCompression::None);
+    }
+
+    #[test]
+    fn write_lzma() {
+        roundtrip(BspCompression::Lzma);
+    }
+}
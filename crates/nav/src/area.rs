@@ -0,0 +1,208 @@
+use binrw::{BinRead, BinResult, binread};
+use glam::Vec3;
+
+use crate::{
+    NavGame,
+    flags::{NavAttributes, NavHidingSpotFlags, NavVisibility, TfNavAttributes},
+};
+
+/// Directions used to index connections, in file order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavDirection {
+    North = 0,
+    East = 1,
+    South = 2,
+    West = 3,
+}
+
+#[binread]
+#[derive(Debug, Clone)]
+#[br(import(version: u32, subversion: u32, game: NavGame))]
+pub struct NavArea {
+    pub id: u32,
+    #[br(parse_with = parse_attributes, args(version))]
+    pub attributes: NavAttributes,
+
+    /// Corner with the lowest x and y
+    pub nw_corner: [f32; 3],
+    /// Corner with the highest x and y
+    pub se_corner: [f32; 3],
+    pub ne_z: f32,
+    pub sw_z: f32,
+
+    /// Ids of connected areas, indexed by [`NavDirection`]
+    #[br(map = |l: [NavIdList; 4]| l.map(|l| l.ids))]
+    pub connections: [Vec<u32>; 4],
+
+    #[br(temp)]
+    hiding_spot_count: u8,
+    #[br(count = hiding_spot_count, args { inner: (version,) })]
+    pub hiding_spots: Vec<NavHidingSpot>,
+
+    #[br(temp, if(version < 15))]
+    approach_area_count: u8,
+    /// Removed in version 15
+    #[br(count = approach_area_count)]
+    pub approach_areas: Vec<NavApproachArea>,
+
+    #[br(temp)]
+    encounter_path_count: u32,
+    #[br(count = encounter_path_count, args { inner: (version,) })]
+    pub encounter_paths: Vec<NavEncounterPath>,
+
+    /// 1-based index into [`crate::NavMesh::places`], 0 if the area has no place
+    #[br(if(version >= 5))]
+    pub place: u16,
+
+    /// Ids of ladders leaving this area, indexed by [`crate::ladder::NavLadderDirection`]
+    #[br(if(version >= 7), map = |l: [NavIdList; 2]| l.map(|l| l.ids))]
+    pub ladders: [Vec<u32>; 2],
+
+    /// Time in seconds until each team can reach this area
+    #[br(if(version >= 8))]
+    pub earliest_occupy_time: [f32; 2],
+
+    /// Light intensity at each corner
+    #[br(if(version >= 11))]
+    pub light_intensity: [f32; 4],
+
+    #[br(temp, if(version >= 16))]
+    visible_area_count: u32,
+    #[br(count = visible_area_count)]
+    pub visible_areas: Vec<NavVisibleArea>,
+    /// Area to copy the visibility set from, 0 if the area stores its own
+    #[br(if(version >= 16))]
+    pub inherit_visibility_from: u32,
+
+    #[br(if(game == NavGame::Tf2 && subversion > 0), map = TfNavAttributes::from_bits_retain)]
+    pub tf_attributes: TfNavAttributes,
+}
+
+impl NavArea {
+    /// Returns the corners in north-west, north-east, south-east, south-west order
+    pub fn corners(&self) -> [Vec3; 4] {
+        let nw = Vec3::from(self.nw_corner);
+        let se = Vec3::from(self.se_corner);
+        [
+            nw,
+            Vec3::new(se.x, nw.y, self.ne_z),
+            se,
+            Vec3::new(nw.x, se.y, self.sw_z),
+        ]
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (Vec3::from(self.nw_corner) + Vec3::from(self.se_corner)) / 2.0
+    }
+
+    /// Whether `point` lies within the area when viewed from above
+    pub fn contains_xy(&self, point: Vec3) -> bool {
+        (self.nw_corner[0]..=self.se_corner[0]).contains(&point.x)
+            && (self.nw_corner[1]..=self.se_corner[1]).contains(&point.y)
+    }
+
+    pub fn connections_in(&self, direction: NavDirection) -> &[u32] {
+        &self.connections[direction as usize]
+    }
+
+    /// Ids of all connected areas, in every direction
+    pub fn connected_areas(&self) -> impl Iterator<Item = u32> + '_ {
+        self.connections.iter().flatten().copied()
+    }
+}
+
+#[binread]
+#[derive(Debug, Clone)]
+#[br(import(version: u32))]
+pub struct NavHidingSpot {
+    /// Version 1 spots don't have an id
+    #[br(if(version >= 2))]
+    pub id: u32,
+    pub position: [f32; 3],
+    #[br(if(version >= 2, NavHidingSpotFlags::IN_COVER), map = NavHidingSpotFlags::from_bits_retain)]
+    pub flags: NavHidingSpotFlags,
+}
+
+#[derive(BinRead, Debug, Clone)]
+pub struct NavApproachArea {
+    pub here: u32,
+    pub prev: u32,
+    /// How to get from `prev` to `here`, a direction or ladder/jump/drop type
+    pub prev_to_here: u8,
+    pub next: u32,
+    pub here_to_next: u8,
+}
+
+/// A path through an area, with the spots that can be seen while walking it
+#[binread]
+#[derive(Debug, Clone)]
+#[br(import(version: u32))]
+pub struct NavEncounterPath {
+    pub from_area: u32,
+    /// Versions before 3 store positions instead of directions
+    #[br(if(version >= 3))]
+    pub from_direction: u8,
+    pub to_area: u32,
+    #[br(if(version >= 3))]
+    pub to_direction: u8,
+    /// Only stored before version 3
+    #[br(if(version < 3))]
+    pub from_position: Option<[f32; 3]>,
+    #[br(if(version < 3))]
+    pub to_position: Option<[f32; 3]>,
+    #[br(temp)]
+    spot_count: u8,
+    #[br(count = spot_count, args { inner: (version,) })]
+    pub spots: Vec<NavEncounterSpot>,
+}
+
+#[binread]
+#[derive(Debug, Clone)]
+#[br(import(version: u32))]
+pub struct NavEncounterSpot {
+    /// Id of the hiding spot, versions before 3 store its position instead
+    #[br(if(version >= 3))]
+    pub spot: u32,
+    #[br(if(version < 3))]
+    pub position: Option<[f32; 3]>,
+    /// Distance along the path, from 0 to 1
+    #[br(parse_with = parse_encounter_distance, args(version))]
+    pub t: f32,
+}
+
+#[derive(BinRead, Debug, Clone)]
+pub struct NavVisibleArea {
+    pub id: u32,
+    #[br(map = NavVisibility::from_bits_retain)]
+    pub visibility: NavVisibility,
+}
+
+#[binread]
+struct NavIdList {
+    #[br(temp)]
+    count: u32,
+    #[br(count = count)]
+    ids: Vec<u32>,
+}
+
+/// Attributes grew from 8 to 16 to 32 bits over time
+#[binrw::parser(reader, endian)]
+fn parse_attributes(version: u32) -> BinResult<NavAttributes> {
+    let bits = match version {
+        ..=8 => u8::read_options(reader, endian, ())? as u32,
+        9..=12 => u16::read_options(reader, endian, ())? as u32,
+        _ => u32::read_options(reader, endian, ())?,
+    };
+
+    Ok(NavAttributes::from_bits_retain(bits))
+}
+
+/// Encounter spot distances were stored as floats before being packed into a byte in version 3
+#[binrw::parser(reader, endian)]
+fn parse_encounter_distance(version: u32) -> BinResult<f32> {
+    if version < 3 {
+        return f32::read_options(reader, endian, ());
+    }
+
+    Ok(u8::read_options(reader, endian, ())? as f32 / 255.0)
+}
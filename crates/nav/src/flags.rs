@@ -0,0 +1,78 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Generic area attributes (`NAV_MESH_*`)
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct NavAttributes: u32 {
+        const CROUCH = 0x1;
+        const JUMP = 0x2;
+        const PRECISE = 0x4;
+        const NO_JUMP = 0x8;
+        const STOP = 0x10;
+        const RUN = 0x20;
+        const WALK = 0x40;
+        const AVOID = 0x80;
+        const TRANSIENT = 0x100;
+        const DONT_HIDE = 0x200;
+        const STAND = 0x400;
+        const NO_HOSTAGES = 0x800;
+        const STAIRS = 0x1000;
+        const NO_MERGE = 0x2000;
+        const OBSTACLE_TOP = 0x4000;
+        const CLIFF = 0x8000;
+        /// Bits 16-26 are reserved for games
+        const FIRST_CUSTOM = 0x10000;
+        const LAST_CUSTOM = 0x4000000;
+        const FUNC_COST = 0x20000000;
+        const HAS_ELEVATOR = 0x40000000;
+        const NAV_BLOCKER = 0x80000000;
+    }
+
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct NavHidingSpotFlags: u8 {
+        const IN_COVER = 0x1;
+        const GOOD_SNIPER_SPOT = 0x2;
+        const IDEAL_SNIPER_SPOT = 0x4;
+        const EXPOSED = 0x8;
+    }
+
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct NavVisibility: u8 {
+        const POTENTIALLY_VISIBLE = 0x1;
+        const COMPLETELY_VISIBLE = 0x2;
+    }
+
+    /// Team Fortress 2 area attributes (`TF_NAV_*`)
+    #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct TfNavAttributes: u32 {
+        const BLOCKED = 0x1;
+        const SPAWN_ROOM_RED = 0x2;
+        const SPAWN_ROOM_BLUE = 0x4;
+        const SPAWN_ROOM_EXIT = 0x8;
+        const HAS_AMMO = 0x10;
+        const HAS_HEALTH = 0x20;
+        const CONTROL_POINT = 0x40;
+        const BLUE_SENTRY_DANGER = 0x80;
+        const RED_SENTRY_DANGER = 0x100;
+        const BLUE_SETUP_GATE = 0x800;
+        const RED_SETUP_GATE = 0x1000;
+        const BLOCKED_AFTER_POINT_CAPTURE = 0x2000;
+        const BLOCKED_UNTIL_POINT_CAPTURE = 0x4000;
+        const BLUE_ONE_WAY_DOOR = 0x8000;
+        const RED_ONE_WAY_DOOR = 0x10000;
+        const WITH_SECOND_POINT = 0x20000;
+        const WITH_THIRD_POINT = 0x40000;
+        const WITH_FOURTH_POINT = 0x80000;
+        const WITH_FIFTH_POINT = 0x100000;
+        const SNIPER_SPOT = 0x200000;
+        const SENTRY_SPOT = 0x400000;
+        const ESCAPE_ROUTE = 0x800000;
+        const ESCAPE_ROUTE_VISIBLE = 0x1000000;
+        const NO_SPAWNING = 0x2000000;
+        const RESCUE_CLOSET = 0x4000000;
+        const BOMB_CAN_DROP_HERE = 0x8000000;
+        const DOOR_NEVER_BLOCKS = 0x10000000;
+        const DOOR_ALWAYS_BLOCKS = 0x20000000;
+        const UNBLOCKABLE = 0x40000000;
+    }
+}
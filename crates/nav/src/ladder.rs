@@ -0,0 +1,41 @@
+use binrw::binread;
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavLadderDirection {
+    Up,
+    Down,
+}
+
+#[binread]
+#[derive(Debug, Clone)]
+#[br(import(version: u32))]
+pub struct NavLadder {
+    pub id: u32,
+    pub width: f32,
+    pub top: [f32; 3],
+    pub bottom: [f32; 3],
+    pub length: f32,
+    /// Direction the climbable side is facing, see [`crate::area::NavDirection`]
+    pub direction: u32,
+    /// Only stored by version 6
+    #[br(temp, if(version == 6))]
+    _dangling: u8,
+
+    /// Area ids, 0 if there's no area
+    pub top_forward_area: u32,
+    pub top_left_area: u32,
+    pub top_right_area: u32,
+    pub top_behind_area: u32,
+    pub bottom_area: u32,
+}
+
+impl NavLadder {
+    pub fn top(&self) -> Vec3 {
+        Vec3::from(self.top)
+    }
+
+    pub fn bottom(&self) -> Vec3 {
+        Vec3::from(self.bottom)
+    }
+}
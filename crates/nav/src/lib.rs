@@ -0,0 +1,233 @@
+use area::NavArea;
+use binrw::{BinReaderExt, binread};
+use eyre::Context;
+use glam::Vec3;
+use ladder::NavLadder;
+use std::io::Cursor;
+
+pub mod area;
+pub mod flags;
+pub mod ladder;
+
+/// Newest version written by Source 2013
+pub const NAV_CURRENT_VERSION: u32 = 16;
+
+/// Games that store extra per-area data, which can't be detected from the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NavGame {
+    #[default]
+    Generic,
+    Tf2,
+}
+
+/// Parsed `maps/*.nav` file
+#[binread]
+#[derive(Debug, Clone)]
+#[br(little, magic = 0xFEEDFACEu32, import(game: NavGame))]
+pub struct NavMesh {
+    #[br(calc = game)]
+    pub game: NavGame,
+
+    #[br(assert(version <= NAV_CURRENT_VERSION, "Unsupported nav version {version}"))]
+    pub version: u32,
+    /// Game specific version, only stored since version 10
+    #[br(if(version >= 10))]
+    pub subversion: u32,
+    /// Size of the bsp the mesh was generated for, used to detect outdated meshes
+    #[br(if(version >= 4))]
+    pub bsp_size: u32,
+    #[br(if(version >= 14), map = |b: u8| b != 0)]
+    pub is_analyzed: bool,
+
+    #[br(temp, if(version >= 5))]
+    place_count: u16,
+    /// Place names, referenced by [`NavArea::place`]
+    #[br(count = place_count, map = |p: Vec<NavPlace>| p.into_iter().map(|p| p.name).collect())]
+    pub places: Vec<String>,
+    #[br(if(version > 11), map = |b: u8| b != 0)]
+    pub has_unnamed_areas: bool,
+
+    #[br(temp)]
+    area_count: u32,
+    #[br(count = area_count, args { inner: (version, subversion, game) })]
+    pub areas: Vec<NavArea>,
+
+    #[br(temp, if(version >= 6))]
+    ladder_count: u32,
+    #[br(count = ladder_count, args { inner: (version,) })]
+    pub ladders: Vec<NavLadder>,
+}
+
+#[binread]
+struct NavPlace {
+    #[br(temp)]
+    length: u16,
+    /// Includes the null terminator
+    #[br(count = length, map = |b: Vec<u8>| String::from_utf8_lossy(&b).trim_end_matches('\0').to_string())]
+    name: String,
+}
+
+impl NavMesh {
+    pub fn parse(data: &[u8], game: NavGame) -> eyre::Result<Self> {
+        Cursor::new(data)
+            .read_le_args((game,))
+            .context("Failed to parse nav mesh")
+    }
+
+    pub fn area(&self, id: u32) -> Option<&NavArea> {
+        self.areas.iter().find(|a| a.id == id)
+    }
+
+    pub fn ladder(&self, id: u32) -> Option<&NavLadder> {
+        self.ladders.iter().find(|l| l.id == id)
+    }
+
+    pub fn place_name(&self, area: &NavArea) -> Option<&str> {
+        let index = (area.place as usize).checked_sub(1)?;
+        self.places.get(index).map(|s| s.as_str())
+    }
+
+    /// Returns the highest area below or around `point`
+    pub fn area_at(&self, point: Vec3) -> Option<&NavArea> {
+        self.areas
+            .iter()
+            .filter(|a| a.contains_xy(point))
+            .filter(|a| a.nw_corner[2].min(a.se_corner[2]) <= point.z)
+            .max_by(|a, b| a.center().z.total_cmp(&b.center().z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{area::NavDirection, flags::TfNavAttributes};
+
+    fn area(data: &mut Vec<u8>, id: u32, min: [f32; 2], connection: u32) {
+        let u32s = |data: &mut Vec<u8>, values: &[u32]| {
+            values.iter().for_each(|v| data.extend(v.to_le_bytes()))
+        };
+        let f32s = |data: &mut Vec<u8>, values: &[f32]| {
+            values.iter().for_each(|v| data.extend(v.to_le_bytes()))
+        };
+
+        // id, attributes (CROUCH), corners
+        u32s(data, &[id, 1]);
+        f32s(
+            data,
+            &[
+                min[0],
+                min[1],
+                0.0,
+                min[0] + 64.0,
+                min[1] + 64.0,
+                0.0,
+                0.0,
+                0.0,
+            ],
+        );
+        // One connection east
+        u32s(data, &[0, 1, connection, 0, 0]);
+        // One hiding spot
+        data.push(1);
+        u32s(data, &[id * 10]);
+        f32s(data, &[min[0] + 32.0, min[1] + 32.0, 0.0]);
+        data.push(2);
+        // One encounter path from and to the neighbour, with one spot
+        u32s(data, &[1, connection]);
+        data.push(1);
+        u32s(data, &[connection]);
+        data.extend([3, 1]);
+        u32s(data, &[id * 10]);
+        data.push(255);
+        // place, ladders up and down
+        data.extend(1u16.to_le_bytes());
+        u32s(data, &[1, 7, 0]);
+        // Earliest occupy times and light intensity
+        f32s(data, &[1.0, 2.0, 1.0, 1.0, 1.0, 1.0]);
+        // Visible areas, inherit visibility from
+        u32s(data, &[1, connection]);
+        data.push(2);
+        u32s(data, &[0]);
+        // TF attributes (HAS_HEALTH)
+        u32s(data, &[0x20]);
+    }
+
+    #[test]
+    fn parse_tf2_mesh() {
+        let mut data = vec![];
+        for v in [0xFEEDFACE, 16, 2, 1234] {
+            data.extend(u32::to_le_bytes(v));
+        }
+        data.push(1);
+        data.extend(1u16.to_le_bytes());
+        data.extend(6u16.to_le_bytes());
+        data.extend(b"Spawn\0");
+        data.push(0);
+
+        data.extend(2u32.to_le_bytes());
+        area(&mut data, 1, [0.0, 0.0], 2);
+        area(&mut data, 2, [64.0, 0.0], 1);
+
+        data.extend(1u32.to_le_bytes());
+        data.extend(7u32.to_le_bytes());
+        for v in [16.0f32, 0.0, 0.0, 128.0, 0.0, 0.0, 0.0, 128.0] {
+            data.extend(v.to_le_bytes());
+        }
+        for v in [0u32, 1, 0, 0, 0, 2] {
+            data.extend(v.to_le_bytes());
+        }
+
+        let mesh = NavMesh::parse(&data, NavGame::Tf2).unwrap();
+        assert_eq!(mesh.places, ["Spawn"]);
+        assert_eq!(mesh.areas.len(), 2);
+        assert_eq!(mesh.ladders[0].bottom_area, 2);
+
+        let area = mesh.area(1).unwrap();
+        assert_eq!(mesh.place_name(area), Some("Spawn"));
+        assert_eq!(area.connections_in(NavDirection::East), [2]);
+        assert_eq!(area.hiding_spots[0].id, 10);
+        assert_eq!(area.encounter_paths[0].spots[0].t, 1.0);
+        assert_eq!(area.ladders, [vec![7], vec![]]);
+        assert_eq!(area.visible_areas[0].id, 2);
+        assert_eq!(area.tf_attributes, TfNavAttributes::HAS_HEALTH);
+
+        assert_eq!(mesh.area_at(Vec3::new(96.0, 32.0, 10.0)).unwrap().id, 2);
+
+        // Generic meshes don't have the TF attributes, so the second area is misaligned
+        assert!(NavMesh::parse(&data, NavGame::Generic).is_err());
+    }
+
+    #[test]
+    fn parse_old_encounter_paths() {
+        let mut data = vec![];
+        for v in [0xFEEDFACE, 2, 1, 1] {
+            data.extend(u32::to_le_bytes(v));
+        }
+        // Attributes, corners, no connections, hiding or approach spots
+        data.push(0);
+        for v in [0.0f32, 0.0, 0.0, 64.0, 64.0, 0.0, 0.0, 0.0] {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend([0; 16]);
+        data.extend([0, 0]);
+
+        // One encounter path with positions instead of directions, and a spot with a float distance
+        for v in [1u32, 3, 4] {
+            data.extend(v.to_le_bytes());
+        }
+        for v in [0.0f32, 32.0, 0.0, 64.0, 32.0, 0.0] {
+            data.extend(v.to_le_bytes());
+        }
+        data.push(1);
+        for v in [32.0f32, 16.0, 0.0, 0.5] {
+            data.extend(v.to_le_bytes());
+        }
+
+        let mesh = NavMesh::parse(&data, NavGame::Generic).unwrap();
+        let path = &mesh.areas[0].encounter_paths[0];
+        assert_eq!((path.from_area, path.to_area), (3, 4));
+        assert_eq!(path.to_position, Some([64.0, 32.0, 0.0]));
+        assert_eq!(path.spots[0].position, Some([32.0, 16.0, 0.0]));
+        assert_eq!(path.spots[0].t, 0.5);
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 #[derive(clap::Parser)]
 #[command(author, version, about)]
 pub struct Args {
@@ -16,4 +18,30 @@ pub struct Args {
     /// Additional VPKs to mount in the virtual filesystem
     #[clap(short, long)]
     pub mount: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Inspect BSP files without opening the viewer
+    Bsp {
+        #[command(subcommand)]
+        command: BspCommand,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum BspCommand {
+    /// Print lump, entity, texture, prop, lightmap and pakfile statistics
+    Info {
+        path: PathBuf,
+
+        /// Print the report as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Compare two versions of a map
+    Diff { old: PathBuf, new: PathBuf },
 }
@@ -0,0 +1,341 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use eyre::Context;
+use powerjack_bsp::{
+    BSP_LUMP_NAMES, Bsp, BspFile, BspLumpReader, diff::BspDiff, lighting::LIGHT_STYLE_UNUSED,
+    pakfile::PakCompression,
+};
+use serde::Serialize;
+
+use crate::args::{BspCommand, Command};
+
+pub fn run(command: &Command) -> eyre::Result<()> {
+    match command {
+        Command::Bsp { command } => match command {
+            BspCommand::Info { path, json } => {
+                let report = BspReport::new(path)?;
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    report.print();
+                }
+            }
+            BspCommand::Diff { old, new } => print_diff(old, new)?,
+        },
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct BspReport {
+    path: String,
+    file_size: u64,
+    version: i32,
+    map_revision: i32,
+    big_endian: bool,
+    lumps: Vec<LumpReport>,
+    game_lumps: Vec<GameLumpReport>,
+    /// Entity count per classname
+    entities: Vec<(String, usize)>,
+    /// Face count per material
+    textures: Vec<(String, usize)>,
+    /// Instance count per static prop model
+    static_props: Vec<(String, usize)>,
+    lightmaps: LightmapReport,
+    displacements: DisplacementReport,
+    pakfile: PakfileReport,
+}
+
+#[derive(Serialize)]
+struct LumpReport {
+    index: usize,
+    name: &'static str,
+    version: i32,
+    offset: u32,
+    length: u32,
+    /// Only set for LZMA compressed lumps
+    uncompressed_length: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct GameLumpReport {
+    id: String,
+    version: u16,
+    length: u32,
+    compressed: bool,
+}
+
+#[derive(Serialize)]
+struct LightmapReport {
+    lit_faces: usize,
+    ldr_bytes: usize,
+    hdr_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct DisplacementReport {
+    count: usize,
+    /// Displacement count for power 2, 3 and 4
+    by_power: [usize; 3],
+    vertices: usize,
+}
+
+#[derive(Serialize)]
+struct PakfileReport {
+    files: Vec<PakfileEntryReport>,
+    size: usize,
+    uncompressed_size: usize,
+}
+
+#[derive(Serialize)]
+struct PakfileEntryReport {
+    name: String,
+    size: u32,
+    compressed_size: usize,
+    lzma: bool,
+}
+
+impl BspReport {
+    fn new(path: &Path) -> eyre::Result<Self> {
+        let file_size = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .len();
+        let mut file = BspFile::new(BufReader::new(
+            File::open(path).context("Failed to open bsp file")?,
+        ))?;
+        let bsp = Bsp::parse(&mut file)?;
+        let pakfile = file.read_pakfile()?;
+
+        let lumps = file
+            .header
+            .lumps
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.length != 0)
+            .map(|(index, l)| LumpReport {
+                index,
+                name: BSP_LUMP_NAMES[index],
+                version: l.version,
+                offset: l.offset,
+                length: l.length,
+                uncompressed_length: l.uncompressed_length(file.header.endian),
+            })
+            .collect();
+
+        let game_lumps = file
+            .game_lumps()?
+            .iter()
+            .filter(|gl| gl.id != 0)
+            .map(|gl| GameLumpReport {
+                id: String::from_utf8_lossy(&gl.id.to_be_bytes()).to_string(),
+                version: gl.version,
+                length: gl.filelen,
+                compressed: gl.flags & 1 != 0,
+            })
+            .collect();
+
+        let entities = bsp.parse_entities()?;
+        let entity_counts = count(
+            entities
+                .iter()
+                .map(|e| e.classname().unwrap_or("<none>").to_string()),
+        );
+
+        let textures = count(
+            bsp.faces
+                .iter()
+                .filter_map(|f| bsp.face_material(f).map(|m| m.to_lowercase())),
+        );
+
+        let static_props = count(bsp.static_props.iter().map(|p| {
+            p.model(&bsp.static_prop_models)
+                .unwrap_or("<invalid>")
+                .to_string()
+        }));
+
+        let lit_faces = bsp
+            .faces
+            .iter()
+            .filter(|f| f.lightmap_data_offset >= 0 && f.styles[0] != LIGHT_STYLE_UNUSED)
+            .count();
+
+        let mut by_power = [0; 3];
+        for disp in &bsp.disp_info {
+            if (2..=4).contains(&disp.power) {
+                by_power[disp.power as usize - 2] += 1;
+            }
+        }
+
+        let files: Vec<_> = pakfile
+            .entries
+            .iter()
+            .map(|e| PakfileEntryReport {
+                name: e.name.clone(),
+                size: e.size,
+                compressed_size: e.compressed_size(),
+                lzma: e.compression == PakCompression::Lzma,
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.display().to_string(),
+            file_size,
+            version: file.header.version,
+            map_revision: file.header.map_revision,
+            big_endian: file.header.endian == binrw::Endian::Big,
+            lumps,
+            game_lumps,
+            entities: entity_counts,
+            textures,
+            static_props,
+            lightmaps: LightmapReport {
+                lit_faces,
+                ldr_bytes: bsp.lightmap_data.len() * 4,
+                hdr_bytes: bsp.lightmap_data_hdr.len() * 4,
+            },
+            displacements: DisplacementReport {
+                count: bsp.disp_info.len(),
+                by_power,
+                vertices: bsp.disp_verts.len(),
+            },
+            pakfile: PakfileReport {
+                size: files.iter().map(|f| f.compressed_size).sum(),
+                uncompressed_size: files.iter().map(|f| f.size as usize).sum(),
+                files,
+            },
+        })
+    }
+
+    fn print(&self) {
+        println!("{} ({})", self.path, format_size(self.file_size as usize));
+        println!(
+            "VBSP version {}, map revision {}{}",
+            self.version,
+            self.map_revision,
+            if self.big_endian { ", big-endian" } else { "" }
+        );
+
+        println!("\nLumps:");
+        for l in &self.lumps {
+            let compression = match l.uncompressed_length {
+                Some(size) => format!(" (LZMA, {} uncompressed)", format_size(size as usize)),
+                None => String::new(),
+            };
+            println!(
+                "  {:>2} {:<32} v{:<2} {:>10}{compression}",
+                l.index,
+                l.name,
+                l.version,
+                format_size(l.length as usize)
+            );
+        }
+        for gl in &self.game_lumps {
+            println!(
+                "     game lump {:<22} v{:<2} {:>10}{}",
+                gl.id,
+                gl.version,
+                format_size(gl.length as usize),
+                if gl.compressed { " (LZMA)" } else { "" }
+            );
+        }
+
+        print_counts("Entities", &self.entities);
+        print_counts("Textures (faces)", &self.textures);
+        print_counts("Static props", &self.static_props);
+
+        println!("\nLightmaps:");
+        println!("  {} lit faces", self.lightmaps.lit_faces);
+        println!("  LDR {}", format_size(self.lightmaps.ldr_bytes));
+        println!("  HDR {}", format_size(self.lightmaps.hdr_bytes));
+
+        let d = &self.displacements;
+        println!("\nDisplacements:");
+        println!(
+            "  {} displacements ({} power 2, {} power 3, {} power 4), {} vertices",
+            d.count, d.by_power[0], d.by_power[1], d.by_power[2], d.vertices
+        );
+
+        let p = &self.pakfile;
+        println!(
+            "\nPakfile: {} files, {} ({} uncompressed)",
+            p.files.len(),
+            format_size(p.size),
+            format_size(p.uncompressed_size)
+        );
+        for f in &p.files {
+            println!(
+                "  {:<64} {:>10}{}",
+                f.name,
+                format_size(f.size as usize),
+                if f.lzma { " (LZMA)" } else { "" }
+            );
+        }
+    }
+}
+
+fn print_diff(old: &Path, new: &Path) -> eyre::Result<()> {
+    let open = |path: &Path| -> eyre::Result<_> {
+        let mut file = BspFile::new(BufReader::new(
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+        ))?;
+        let bsp = Bsp::parse(&mut file)?;
+        Ok((file.header, bsp))
+    };
+
+    let (old_header, old) = open(old)?;
+    let (new_header, new) = open(new)?;
+    let diff = BspDiff::new(&old, &new)?.with_headers(&old_header, &new_header);
+    if diff.is_empty() {
+        println!("No differences");
+        return Ok(());
+    }
+
+    fn section<T: std::fmt::Display>(title: &str, changes: &[T]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        println!("\n{title} ({}):", changes.len());
+        for change in changes {
+            println!("  {change}");
+        }
+    }
+
+    section("Lumps", &diff.lumps);
+    section("Entities", &diff.entities);
+    section("Static props", &diff.static_props);
+    section("Textures", &diff.textures);
+
+    Ok(())
+}
+
+/// Counts occurrences, sorted by count and then name
+fn count(items: impl Iterator<Item = String>) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        *counts.entry(item).or_default() += 1;
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+fn print_counts(title: &str, counts: &[(String, usize)]) {
+    let total: usize = counts.iter().map(|(_, c)| c).sum();
+    println!("\n{title}: {total} total, {} unique", counts.len());
+    for (name, count) in counts {
+        println!("  {count:>6} {name}");
+    }
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.2} MiB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.2} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{bytes} B")
+    }
+}
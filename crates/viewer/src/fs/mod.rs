@@ -1,16 +1,11 @@
-use std::{
-    fs::File,
-    io::{BufReader, Cursor},
-    path::Path,
-    sync::Arc,
-};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
 
 use parking_lot::Mutex;
+use powerjack_bsp::pakfile::PakFile;
 use powerjack_vpk::VpkFile;
-use zip_lzma::ZipArchive;
 
+pub mod pakfile;
 pub mod vpk;
-pub mod zip;
 
 pub trait Mountable: Send + Sync {
     fn read_path(&mut self, path: &str) -> eyre::Result<Option<Vec<u8>>>;
@@ -43,9 +38,8 @@ impl Filesystem {
         Ok(())
     }
 
-    pub fn mount_zip(&mut self, zip: Vec<u8>) -> eyre::Result<()> {
-        self.add_mount(Box::new(ZipArchive::new(Cursor::new(zip))?));
-        Ok(())
+    pub fn mount_pakfile(&mut self, pakfile: PakFile) {
+        self.add_mount(Box::new(pakfile));
     }
 
     pub fn read_path(&mut self, path: &str) -> eyre::Result<Option<Vec<u8>>> {
@@ -0,0 +1,8 @@
+use crate::fs::Mountable;
+use powerjack_bsp::pakfile::PakFile;
+
+impl Mountable for PakFile {
+    fn read_path(&mut self, path: &str) -> eyre::Result<Option<Vec<u8>>> {
+        self.read(path)
+    }
+}
@@ -1,4 +1,10 @@
-use std::{fs::File, io::Cursor, path::PathBuf, rc::Rc, sync::Arc, time::Instant};
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::Instant,
+};
 
 use clap::Parser;
 use eyre::Context;
@@ -19,6 +25,7 @@ use crate::{
 };
 
 pub mod args;
+pub mod cli;
 pub mod entities;
 pub mod fs;
 pub mod kv;
@@ -43,6 +50,10 @@ fn main() -> eyre::Result<()> {
     .expect("Failed to set global tracing subscriber");
 
     let args = args::Args::parse();
+    if let Some(command) = &args.command {
+        return cli::run(command);
+    }
+
     let tf2_path = if let Some(path) = args.install_dir {
         path.clone()
     } else if let Some(InstalledGame::Steam(appstate)) =
@@ -120,10 +131,7 @@ fn main() -> eyre::Result<()> {
 
     let mut renderer = renderer::Renderer::new(&window, &fs)?;
     let mut bsp = if let Some(bsp_path) = &args.bsp {
-        let bsp = BspStaticRenderer::load(
-            File::open(bsp_path).context("Failed to open bsp file")?,
-            &renderer,
-        )?;
+        let bsp = BspStaticRenderer::load(Path::new(bsp_path), &renderer)?;
 
         if let Some(sky_camera_ent) = bsp
             .entities
@@ -154,13 +162,7 @@ fn main() -> eyre::Result<()> {
         }
 
         for prop in &bsp.data.static_props {
-            let pitch = Quat::from_axis_angle(Vec3::Y, prop.angles[0].to_radians());
-            let yaw = Quat::from_axis_angle(Vec3::Z, prop.angles[1].to_radians());
-            let roll = Quat::from_axis_angle(Vec3::X, prop.angles[2].to_radians());
-            static_props.push((
-                prop.model_index as usize,
-                Mat4::from_rotation_translation(yaw * pitch * roll, prop.origin.into()),
-            ));
+            static_props.push((prop.model_index as usize, prop.transform()));
         }
     }
 
@@ -1,14 +1,10 @@
-use std::{
-    f32,
-    io::{Read, Seek},
-    ops::Range,
-};
+use std::{f32, fs::File, io::BufReader, ops::Range, path::Path};
 
 use bitflags::bitflags;
 use bytemuck::{Pod, Zeroable};
 use eyre::Context;
-use glam::{IVec2, Mat4, Vec2, Vec3, Vec4, Vec4Swizzles, vec2};
-use powerjack_bsp::{Bsp, BspFile};
+use glam::{IVec2, Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use powerjack_bsp::{Bsp, BspFile, validate::BspSeverity};
 use serde::Deserialize;
 use wgpu::util::DeviceExt;
 
@@ -35,13 +31,39 @@ pub struct BspStaticRenderer {
 }
 
 impl BspStaticRenderer {
-    pub fn load<R: Read + Seek>(reader: R, renderer: &Renderer) -> eyre::Result<Self> {
+    pub fn load(path: &Path, renderer: &Renderer) -> eyre::Result<Self> {
         let iad = &renderer.iad;
 
-        let mut file = BspFile::new(reader)?;
+        let mut file = BspFile::new(BufReader::new(
+            File::open(path).context("Failed to open bsp file")?,
+        ))?;
+        let lump_files = file.apply_lump_files(path);
+        for (lump_file, e) in &lump_files.skipped {
+            warn!("Skipping lump file {}: {e}", lump_file.display());
+        }
+        if !lump_files.applied.is_empty() {
+            info!("Applied {} lump file(s)", lump_files.applied.len());
+        }
         let bsp = Bsp::parse(&mut file)?;
-        let pakfile = file.read_lump_raw(40)?;
-        renderer.fs.lock().mount_zip(pakfile)?;
+        if let Some(e) = &bsp.phys_collide_error {
+            warn!("Failed to parse physcollide lump: {e}");
+        }
+        let mut errors = 0;
+        for diagnostic in bsp.validate() {
+            match diagnostic.severity() {
+                BspSeverity::Warning => warn!("{diagnostic}"),
+                BspSeverity::Error => {
+                    error!("{diagnostic}");
+                    errors += 1;
+                }
+            }
+        }
+        if errors > 0 {
+            eyre::bail!("Map failed validation with {errors} error(s)");
+        }
+
+        let pakfile = file.read_pakfile()?;
+        renderer.fs.lock().mount_pakfile(pakfile);
 
         let mut gpu_faces = Vec::with_capacity(bsp.faces.len());
         let mut face_vertices: Vec<StaticMapVertex> = vec![];
@@ -49,6 +71,11 @@ impl BspStaticRenderer {
         let mut indices: Vec<u32> = vec![];
         let mut i = 0;
         let model = bsp.models.first().unwrap();
+
+        // HDR-only maps don't have any LDR lighting
+        let hdr = bsp.lightmap_data.is_empty() && bsp.has_hdr_lighting();
+        let lightmap_faces = bsp.lightmap_faces(hdr);
+        let displacements = bsp.build_displacements();
         for (fi, f) in bsp.faces
             [model.first_face as usize..(model.first_face + model.num_faces) as usize]
             .iter()
@@ -73,7 +100,11 @@ impl BspStaticRenderer {
                 gpu_faces.push(GpuMapFace {
                     lightmap_face_size_packed: (lightmap_face_size.x as u32 & 0xFFFF) << 16
                         | (lightmap_face_size.y as u32 & 0xFFFF),
-                    lightmap_offset: f.lightmap_data_offset / 4,
+                    lightmap_offset: lightmap_faces
+                        .get(model.first_face as usize + fi)
+                        .unwrap_or(f)
+                        .lightmap_data_offset
+                        / 4,
                     flags,
                 });
             }
@@ -105,125 +136,37 @@ impl BspStaticRenderer {
             }
 
             if f.disp_info != -1 {
-                let dispinfo = &bsp.disp_info[f.disp_info as usize];
-                let low_base = Vec3::from(dispinfo.start_position);
-                if f.num_edges != 4 {
+                let Some(Some(disp)) = displacements.get(f.disp_info as usize) else {
                     error!("Bad displacement (face {fi})");
                     continue;
-                }
-
-                let mut corner_verts = [Vec3::ZERO; 4];
-                let mut base_i = 0;
-                let mut base_dist = f32::INFINITY;
-                for (k, vert) in corner_verts.iter_mut().enumerate() {
-                    let edge = bsp.surfedges[f.first_edge as usize + k];
-                    let e = if edge < 0 {
-                        bsp.edges[edge.unsigned_abs() as usize][0] as u32
-                    } else {
-                        bsp.edges[edge.unsigned_abs() as usize][1] as u32
-                    };
-
-                    *vert = bsp.vertices[e as usize].into();
-                    let this_dist = (vert.x - low_base.x).abs()
-                        + (vert.y - low_base.y).abs()
-                        + (vert.z - low_base.z).abs();
-                    if this_dist < base_dist {
-                        base_dist = this_dist;
-                        base_i = k;
-                    }
-                }
+                };
 
-                let high_base = corner_verts[(base_i + 3) % 4];
-                let high_ray = corner_verts[(base_i + 2) % 4] - high_base;
-                let low_ray = corner_verts[(base_i + 1) % 4] - low_base;
-                let verts_wide = ((2 << (dispinfo.power - 1)) + 1) as usize;
-                let mut base_verts = vec![Vec3::ZERO; verts_wide * verts_wide];
-                let mut base_vert_luvs = vec![Vec2::ZERO; verts_wide * verts_wide];
-                let mut base_alphas = vec![0.0; verts_wide * verts_wide];
-                let base_dispvert = dispinfo.disp_vert_start.unsigned_abs() as usize;
-
-                for y in 0..verts_wide {
-                    let fy = y as f32 / (verts_wide as f32 - 1.0);
-
-                    let mid_base = low_base + low_ray * fy;
-                    let mid_ray = high_base + high_ray * fy - mid_base;
-
-                    for x in 0..verts_wide {
-                        let fx = x as f32 / (verts_wide as f32 - 1.0);
-                        let ii = y * verts_wide + x;
-
-                        let vert = &bsp.disp_verts[base_dispvert + ii];
-                        let offset = Vec3::from(vert.vec);
-                        let scale = vert.dist;
-                        let alpha = vert.alpha / 255.0;
-
-                        base_verts[ii] = mid_base + mid_ray * fx + offset * scale;
-                        base_vert_luvs[ii] = vec2(
-                            lightmap_face_size.x as f32 * fx - 1.0,
-                            lightmap_face_size.y as f32 * fy - 1.0,
-                        );
-                        base_alphas[ii] = alpha;
-                    }
+                for (((&v, &n), &uv), &alpha) in disp
+                    .positions
+                    .iter()
+                    .zip(&disp.normals)
+                    .zip(&disp.uvs)
+                    .zip(&disp.alphas)
+                {
+                    let luv = lightmap_face_size.as_vec2() * uv - 1.0;
+                    add_vert!(v, luv, alpha, n);
                 }
 
-                for y in 0..(verts_wide - 1) {
-                    for x in 0..(verts_wide - 1) {
-                        let ii = y * verts_wide + x;
-
-                        let v0 = base_verts[ii];
-                        let v1 = base_verts[ii + 1];
-                        let v2 = base_verts[ii + verts_wide];
-                        let v3 = base_verts[ii + verts_wide + 1];
-
-                        let v0_luv = base_vert_luvs[ii];
-                        let v1_luv = base_vert_luvs[ii + 1];
-                        let v2_luv = base_vert_luvs[ii + verts_wide];
-                        let v3_luv = base_vert_luvs[ii + verts_wide + 1];
-
-                        let v0_alpha = base_alphas[ii];
-                        let v1_alpha = base_alphas[ii + 1];
-                        let v2_alpha = base_alphas[ii + verts_wide];
-                        let v3_alpha = base_alphas[ii + verts_wide + 1];
-
-                        add_vert!(v0, v0_luv, v0_alpha, normal);
-                        add_vert!(v1, v1_luv, v1_alpha, normal);
-                        add_vert!(v2, v2_luv, v2_alpha, normal);
-                        add_vert!(v3, v3_luv, v3_alpha, normal);
-
-                        if ii.is_multiple_of(2) {
-                            indices.extend_from_slice(&[i, i + 1, i + 2, i + 1, i + 3, i + 2]);
-                        } else {
-                            indices.extend_from_slice(&[i, i + 3, i + 2, i + 1, i + 3, i]);
-                        }
-
-                        i += 4;
-                    }
-                }
+                indices.extend(disp.indices.iter().map(|&index| i + index));
+                i += disp.positions.len() as u32;
             } else {
-                let mut face_indices = vec![];
-                for i in 0..f.num_edges as usize {
-                    let edge = bsp.surfedges[f.first_edge as usize + i];
-                    let e = if edge < 0 {
-                        bsp.edges[edge.unsigned_abs() as usize][0] as u32
-                    } else {
-                        bsp.edges[edge.unsigned_abs() as usize][1] as u32
-                    };
-                    face_indices.push(e);
+                let vertices = bsp.face_vertices(f);
+                for &v in &vertices {
+                    add_vert!(v, Vec2::ZERO, 0.0, normal);
                 }
 
-                for ii in 2..face_indices.len() {
-                    let v0: Vec3 = bsp.vertices[face_indices[ii] as usize].into();
-                    let v1: Vec3 = bsp.vertices[face_indices[ii - 1] as usize].into();
-                    let v2: Vec3 = bsp.vertices[face_indices[0] as usize].into();
-
-                    add_vert!(v0, Vec2::ZERO, 0.0, normal);
-                    add_vert!(v1, Vec2::ZERO, 0.0, normal);
-                    add_vert!(v2, Vec2::ZERO, 0.0, normal);
-
-                    indices.extend_from_slice(&[i, i + 1, i + 2]);
-
-                    i += 3;
-                }
+                // Source faces are clockwise
+                indices.extend(
+                    bsp.face_triangles(f)
+                        .chunks_exact(3)
+                        .flat_map(|t| [i + t[0], i + t[2], i + t[1]]),
+                );
+                i += vertices.len() as u32;
             }
 
             for v in &mut face_vertices[face_data_start..] {
@@ -283,7 +226,7 @@ impl BspStaticRenderer {
         });
 
         let mut lightmap_data = bsp
-            .lightmap_data
+            .lightmap_samples(hdr)
             .iter()
             .map(|t| {
                 let exponent_quantized = (t.exponent as i32 + 127) as u8;